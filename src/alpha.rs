@@ -0,0 +1,234 @@
+//! Cleans up the transparent edges of resized frames before they are encoded.
+//!
+//! Lossy WebP stores the colors with half the resolution, so the colors of transparent
+//! pixels leak into the visible edge and show up as dark or colored fringes.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config::AlphaCleanup;
+use crate::image::{png_size, read_png, write_png, Image};
+
+/// What the cleanup did to the frames of an emote
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CleanupRecord {
+    pub frames: usize,
+    /// Transparent pixels that got the color of their visible neighbours
+    pub bled_pixels: u64,
+    /// Pixels whose alpha was snapped to fully transparent or opaque
+    pub thresholded_pixels: u64,
+    /// PNG bytes of all frames, only a hint at the size of the encoded WebP
+    #[serde(alias = "size_before")]
+    pub png_size_before: u64,
+    #[serde(alias = "size_after")]
+    pub png_size_after: u64,
+    /// Estimated color error of visible pixels after lossy encoding, lower means less halo.
+    /// It averages the chroma of 2x2 blocks like the encoder does, without encoding
+    pub chroma_error_before: f64,
+    pub chroma_error_after: f64,
+}
+
+/// Sets alpha values up to `threshold` to transparent and the rest to opaque
+fn binarize(image: &mut Image, threshold: u8) -> u64 {
+    let mut changed = 0;
+    for px in image.rgba.chunks_exact_mut(4) {
+        let alpha = if px[3] > threshold { 255 } else { 0 };
+        if px[3] != alpha {
+            px[3] = alpha;
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// Gives every transparent pixel the average color of its nearest visible pixels,
+/// spreading outwards one pixel per pass
+fn bleed(image: &mut Image) -> u64 {
+    let (width, height) = (image.width, image.height);
+    let rgba = &mut image.rgba;
+    let mut known: Vec<bool> = rgba.chunks_exact(4).map(|px| px[3] > 0).collect();
+    let neighbours = |index: usize| {
+        let (x, y) = ((index % width) as isize, (index / width) as isize);
+        (-1..=1)
+            .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .filter(move |&(nx, ny)| {
+                (nx, ny) != (x, y)
+                    && (0..width as isize).contains(&nx)
+                    && (0..height as isize).contains(&ny)
+            })
+            .map(move |(nx, ny)| ny as usize * width + nx as usize)
+    };
+
+    let mut frontier: Vec<usize> = (0..known.len())
+        .filter(|&i| !known[i] && neighbours(i).any(|n| known[n]))
+        .collect();
+    let mut bled = 0;
+    while !frontier.is_empty() {
+        let mut colors = Vec::with_capacity(frontier.len());
+        for &i in &frontier {
+            let (mut sum, mut count) = ([0u32; 3], 0);
+            for n in neighbours(i).filter(|&n| known[n]) {
+                for (channel, total) in sum.iter_mut().enumerate() {
+                    *total += rgba[n * 4 + channel] as u32;
+                }
+                count += 1;
+            }
+            colors.push(sum.map(|total| (total / count) as u8));
+        }
+        for (&i, color) in frontier.iter().zip(colors) {
+            rgba[i * 4..i * 4 + 3].copy_from_slice(&color);
+            known[i] = true;
+        }
+        bled += frontier.len() as u64;
+
+        let mut next: Vec<usize> = frontier
+            .iter()
+            .flat_map(|&i| neighbours(i))
+            .filter(|&n| !known[n])
+            .collect();
+        next.sort_unstable();
+        next.dedup();
+        frontier = next;
+    }
+    bled
+}
+
+/// Sum and weight of the chroma error of visible pixels when the chroma of 2x2 blocks is averaged
+fn chroma_error(image: &Image) -> (f64, f64) {
+    let chroma = |px: &[u8]| {
+        let [r, g, b] = [px[0], px[1], px[2]].map(f64::from);
+        (
+            -0.168736 * r - 0.331264 * g + 0.5 * b,
+            0.5 * r - 0.418688 * g - 0.081312 * b,
+        )
+    };
+    let (mut error, mut weight) = (0.0, 0.0);
+    for by in (0..image.height).step_by(2) {
+        for bx in (0..image.width).step_by(2) {
+            let block: Vec<&[u8]> = (by..(by + 2).min(image.height))
+                .flat_map(|y| (bx..(bx + 2).min(image.width)).map(move |x| (x, y)))
+                .map(|(x, y)| &image.rgba[(y * image.width + x) * 4..][..4])
+                .collect();
+            let n = block.len() as f64;
+            let (cb, cr) = block
+                .iter()
+                .map(|px| chroma(px))
+                .fold((0.0, 0.0), |acc, c| (acc.0 + c.0 / n, acc.1 + c.1 / n));
+            for px in &block {
+                let alpha = px[3] as f64 / 255.0;
+                let (pcb, pcr) = chroma(px);
+                error += alpha * ((pcb - cb).abs() + (pcr - cr).abs());
+                weight += alpha;
+            }
+        }
+    }
+    (error, weight)
+}
+
+fn clean_frames_blocking(frames: &[PathBuf], settings: &AlphaCleanup) -> Result<CleanupRecord> {
+    let mut record = CleanupRecord {
+        frames: frames.len(),
+        ..Default::default()
+    };
+    let (mut error_before, mut error_after, mut weight_before, mut weight_after) =
+        (0.0, 0.0, 0.0, 0.0);
+    for path in frames {
+        let mut image = read_png(path)?;
+        record.png_size_before += png_size(&image)?;
+        let (error, weight) = chroma_error(&image);
+        error_before += error;
+        weight_before += weight;
+
+        if let Some(threshold) = settings.threshold {
+            record.thresholded_pixels += binarize(&mut image, threshold);
+        }
+        if settings.bleed {
+            record.bled_pixels += bleed(&mut image);
+        }
+
+        let (error, weight) = chroma_error(&image);
+        error_after += error;
+        weight_after += weight;
+        record.png_size_after += png_size(&image)?;
+        write_png(&image, path)?;
+    }
+    record.chroma_error_before = error_before / weight_before.max(1.0);
+    record.chroma_error_after = error_after / weight_after.max(1.0);
+    Ok(record)
+}
+
+/// Thresholds the alpha and bleeds the colors of the PNG frames in place
+pub async fn clean_frames(frames: Vec<PathBuf>, settings: &AlphaCleanup) -> Result<CleanupRecord> {
+    let settings = settings.clone();
+    tokio::task::spawn_blocking(move || clean_frames_blocking(&frames, &settings)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `width`x`height` image of transparent black with the given pixels set
+    fn canvas(width: usize, height: usize, pixels: &[(usize, [u8; 4])]) -> Image {
+        let mut rgba = vec![0; width * height * 4];
+        for (index, px) in pixels {
+            rgba[index * 4..][..4].copy_from_slice(px);
+        }
+        Image {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    #[test]
+    fn binarize_snaps_alpha() {
+        let mut image = canvas(
+            4,
+            1,
+            &[(1, [9, 9, 9, 100]), (2, [9, 9, 9, 255]), (3, [9; 4])],
+        );
+        // the fully transparent and the fully opaque pixel don't change
+        let changed = binarize(&mut image, 9);
+        assert_eq!(changed, 2);
+        let alpha: Vec<u8> = image.rgba.chunks_exact(4).map(|px| px[3]).collect();
+        assert_eq!(alpha, [0, 255, 255, 0]);
+    }
+
+    #[test]
+    fn bleed_spreads_the_visible_colors() {
+        let mut image = canvas(3, 1, &[(0, [200, 0, 0, 255]), (2, [0, 0, 100, 255])]);
+        assert_eq!(bleed(&mut image), 1);
+        assert_eq!(&image.rgba[4..8], [100, 0, 50, 0]);
+
+        let mut image = canvas(4, 1, &[(0, [200, 40, 0, 255])]);
+        assert_eq!(bleed(&mut image), 3);
+        for px in image.rgba.chunks_exact(4).skip(1) {
+            assert_eq!(px, [200, 40, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn bleed_leaves_transparent_images_alone() {
+        let mut image = canvas(2, 2, &[]);
+        assert_eq!(bleed(&mut image), 0);
+        assert!(image.rgba.iter().all(|&c| c == 0));
+    }
+
+    #[test]
+    fn chroma_error_counts_visible_pixels() {
+        let red = [255, 0, 0, 255];
+        let (error, weight) =
+            chroma_error(&canvas(2, 2, &[(0, red), (1, red), (2, red), (3, red)]));
+        assert_eq!((error, weight), (0.0, 4.0));
+
+        // black behind the transparent pixels pulls the chroma of the red ones off
+        let (dark, weight) = chroma_error(&canvas(2, 2, &[(0, red)]));
+        assert!(dark > 0.0);
+        assert_eq!(weight, 1.0);
+
+        let (bled, _) = chroma_error(&canvas(2, 2, &[(0, red), (1, [255, 0, 0, 0])]));
+        assert!(bled < dark);
+    }
+}
@@ -0,0 +1,287 @@
+//! Changes the order and the timing of the frames of animated emotes
+
+use crate::config::{Animation, Limits, RetimePolicy};
+use crate::error::ValidationError;
+
+/// Lengthens frames that are too short, then speeds up the longer frames if the emote
+/// is too long.
+///
+/// Frames are never shortened below the minimum, so the total only fits if the minimum
+/// allows it. Returns whether any duration changed.
+pub fn clamp_durations(durations: &mut [i32], limits: &Limits) -> bool {
+    let original = durations.to_vec();
+    let min = limits.min_frame_duration_ms;
+    durations.iter_mut().for_each(|d| *d = (*d).max(min));
+
+    // every round either fits or pins another frame at the minimum
+    loop {
+        let total = durations.iter().sum::<i32>();
+        let longer = durations.iter().filter(|&&d| d > min).sum::<i32>();
+        if total <= limits.max_total_duration_ms || longer == 0 {
+            break;
+        }
+        let budget = (limits.max_total_duration_ms - (total - longer)).max(0);
+        let factor = budget as f64 / longer as f64;
+        for d in durations.iter_mut().filter(|d| **d > min) {
+            *d = ((*d as f64 * factor) as i32).max(min);
+        }
+    }
+    durations != original.as_slice()
+}
+
+/// Which frames are shown for how long, `frames` are indices of the extracted frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timeline {
+    pub frames: Vec<usize>,
+    pub durations: Vec<i32>,
+}
+
+impl Timeline {
+    /// Every frame once, in order
+    pub fn new(durations: &[i32]) -> Self {
+        Timeline {
+            frames: (0..durations.len()).collect(),
+            durations: durations.to_vec(),
+        }
+    }
+
+    /// `frames` evenly timed frames of a generated motion, checked against the duration rules
+    pub fn motion(
+        frames: usize,
+        duration_ms: i32,
+        limits: &Limits,
+        policy: RetimePolicy,
+    ) -> Result<Self, ValidationError> {
+        let duration = (duration_ms / frames as i32).max(1);
+        let mut timeline = Timeline::new(&vec![duration; frames]);
+        timeline.check(Some("motion"), limits, policy)?;
+        Ok(timeline)
+    }
+
+    /// Retimes the frames or fails if `step`, or the source when there is none, broke the
+    /// duration rules
+    fn check(
+        &mut self,
+        step: Option<&'static str>,
+        limits: &Limits,
+        policy: RetimePolicy,
+    ) -> Result<(), ValidationError> {
+        let Some(&min) = self.durations.iter().min() else {
+            return Ok(());
+        };
+        let total = self.durations.iter().sum::<i32>();
+        let broken = if min < limits.min_frame_duration_ms {
+            ValidationError::FrameTooShort(min)
+        } else if total > limits.max_total_duration_ms {
+            ValidationError::TooLong(total)
+        } else {
+            return Ok(());
+        };
+        match policy {
+            RetimePolicy::Clamp => {
+                clamp_durations(&mut self.durations, limits);
+                Ok(())
+            }
+            RetimePolicy::Reject => match step {
+                Some(step) => Err(ValidationError::Transform(step, Box::new(broken))),
+                None => Err(broken),
+            },
+        }
+    }
+
+    /// Applies the frame range, reverse, boomerang and speed of `animation` in that order,
+    /// each one is checked against the duration rules, and so is the source without any of them
+    pub fn transform(
+        mut self,
+        animation: &Animation,
+        limits: &Limits,
+        policy: RetimePolicy,
+    ) -> Result<Self, ValidationError> {
+        if let Some((first, last)) = animation.range {
+            let len = self.frames.len();
+            if first > last || last >= len {
+                return Err(ValidationError::FrameRange(first, last, len));
+            }
+            self.frames = self.frames[first..=last].to_vec();
+            self.durations = self.durations[first..=last].to_vec();
+            self.check(Some("range"), limits, policy)?;
+        }
+        if animation.reverse {
+            self.frames.reverse();
+            self.durations.reverse();
+            self.check(Some("reverse"), limits, policy)?;
+        }
+        if animation.boomerang && self.frames.len() > 2 {
+            // the first and the last frame aren't repeated at the turning points
+            let len = self.frames.len();
+            let frames: Vec<usize> = self.frames[1..len - 1].iter().rev().copied().collect();
+            let durations: Vec<i32> = self.durations[1..len - 1].iter().rev().copied().collect();
+            self.frames.extend(frames);
+            self.durations.extend(durations);
+            self.check(Some("boomerang"), limits, policy)?;
+        }
+        if animation.speed != 1.0 {
+            for duration in &mut self.durations {
+                *duration = ((*duration as f64 / animation.speed).round() as i32).max(1);
+            }
+            self.check(Some("speed"), limits, policy)?;
+        }
+        // a no-op after any step, which already left the durations valid
+        self.check(None, limits, policy)?;
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> Limits {
+        Limits {
+            min_frame_duration_ms: 20,
+            max_total_duration_ms: 1000,
+            ..Default::default()
+        }
+    }
+
+    fn transform(
+        durations: &[i32],
+        animation: Animation,
+        policy: RetimePolicy,
+    ) -> Result<Timeline, ValidationError> {
+        Timeline::new(durations).transform(&animation, &limits(), policy)
+    }
+
+    #[test]
+    fn clamp_fits_the_total() {
+        let defaults = Limits {
+            min_frame_duration_ms: 8,
+            max_total_duration_ms: 10_000,
+            ..Default::default()
+        };
+        let mut durations = vec![8; 1000];
+        durations.push(5000);
+        assert!(clamp_durations(&mut durations, &defaults));
+        assert!(durations[..1000].iter().all(|&d| d == 8));
+        assert_eq!(durations[1000], 2000);
+        assert_eq!(durations.iter().sum::<i32>(), 10_000);
+
+        let mut durations = vec![5, 600, 900];
+        assert!(clamp_durations(&mut durations, &limits()));
+        assert_eq!(durations, [20, 392, 588]);
+        assert!(durations.iter().sum::<i32>() <= 1000);
+    }
+
+    #[test]
+    fn range() {
+        let animation = Animation {
+            range: Some((1, 2)),
+            ..Default::default()
+        };
+        let timeline = transform(&[100, 200, 300, 400], animation, RetimePolicy::Reject).unwrap();
+        assert_eq!(timeline.frames, [1, 2]);
+        assert_eq!(timeline.durations, [200, 300]);
+    }
+
+    #[test]
+    fn range_out_of_bounds() {
+        let animation = Animation {
+            range: Some((2, 4)),
+            ..Default::default()
+        };
+        let err = transform(&[100, 100, 100, 100], animation, RetimePolicy::Clamp).unwrap_err();
+        assert_eq!(err, ValidationError::FrameRange(2, 4, 4));
+    }
+
+    #[test]
+    fn range_cuts_too_long_source() {
+        // 1500ms breaks the total, the first two frames alone don't
+        let animation = Animation {
+            range: Some((0, 1)),
+            ..Default::default()
+        };
+        let timeline = transform(&[500, 500, 500], animation, RetimePolicy::Reject).unwrap();
+        assert_eq!(timeline.durations, [500, 500]);
+    }
+
+    #[test]
+    fn reverse() {
+        let animation = Animation {
+            reverse: true,
+            ..Default::default()
+        };
+        let timeline = transform(&[100, 200, 300], animation, RetimePolicy::Reject).unwrap();
+        assert_eq!(timeline.frames, [2, 1, 0]);
+        assert_eq!(timeline.durations, [300, 200, 100]);
+    }
+
+    #[test]
+    fn boomerang() {
+        let animation = Animation {
+            boomerang: true,
+            ..Default::default()
+        };
+        let timeline = transform(&[50, 100, 150, 200], animation, RetimePolicy::Reject).unwrap();
+        assert_eq!(timeline.frames, [0, 1, 2, 3, 2, 1]);
+        assert_eq!(timeline.durations, [50, 100, 150, 200, 150, 100]);
+    }
+
+    #[test]
+    fn boomerang_too_long() {
+        let animation = Animation {
+            boomerang: true,
+            ..Default::default()
+        };
+        let err = transform(&[200, 200, 200, 200], animation, RetimePolicy::Reject).unwrap_err();
+        let broken = Box::new(ValidationError::TooLong(1200));
+        assert_eq!(err, ValidationError::Transform("boomerang", broken));
+    }
+
+    #[test]
+    fn speed() {
+        let animation = Animation {
+            speed: 2.0,
+            ..Default::default()
+        };
+        let timeline = transform(&[100, 250, 300], animation, RetimePolicy::Reject).unwrap();
+        assert_eq!(timeline.frames, [0, 1, 2]);
+        assert_eq!(timeline.durations, [50, 125, 150]);
+    }
+
+    #[test]
+    fn speed_fixes_too_long_source() {
+        let animation = Animation {
+            speed: 2.0,
+            ..Default::default()
+        };
+        let timeline = transform(&[600, 600, 600], animation, RetimePolicy::Reject).unwrap();
+        assert_eq!(timeline.durations, [300, 300, 300]);
+    }
+
+    #[test]
+    fn speed_too_fast() {
+        let animation = Animation {
+            speed: 10.0,
+            ..Default::default()
+        };
+        let err = transform(&[100, 100], animation, RetimePolicy::Reject).unwrap_err();
+        let broken = Box::new(ValidationError::FrameTooShort(10));
+        assert_eq!(err, ValidationError::Transform("speed", broken));
+
+        let animation = Animation {
+            speed: 10.0,
+            ..Default::default()
+        };
+        let timeline = transform(&[100, 100], animation, RetimePolicy::Clamp).unwrap();
+        assert_eq!(timeline.durations, [20, 20]);
+    }
+
+    #[test]
+    fn untransformed_source_is_checked() {
+        let err = transform(&[600, 600], Animation::default(), RetimePolicy::Reject).unwrap_err();
+        assert_eq!(err, ValidationError::TooLong(1200));
+
+        let timeline = transform(&[10, 100], Animation::default(), RetimePolicy::Clamp).unwrap();
+        assert_eq!(timeline.durations, [20, 100]);
+    }
+}
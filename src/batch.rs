@@ -0,0 +1,187 @@
+use std::future::Future;
+use std::path::PathBuf;
+
+use futures::StreamExt;
+use log::warn;
+
+use crate::emote_ext::EmoteId;
+use crate::error::{Error, Stage};
+
+pub struct BatchElement<T> {
+    pub id: EmoteId,
+    pub result: Result<T, Error>,
+}
+
+/// Runs `f` for every item with at most `par` calls in flight.
+///
+/// Every element is collected unless `fail_fast` is set, in which case
+/// the batch stops at the first error and the remaining items are dropped.
+pub async fn run<I, T, F, Fut>(
+    items: impl IntoIterator<Item = I>,
+    par: usize,
+    fail_fast: bool,
+    f: F,
+) -> Vec<BatchElement<T>>
+where
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = BatchElement<T>>,
+{
+    let mut iter = futures::stream::iter(items).map(f).buffer_unordered(par);
+
+    let mut elements = Vec::new();
+    while let Some(element) = iter.next().await {
+        let failed = element.result.is_err();
+        elements.push(element);
+        if failed && fail_fast {
+            break;
+        }
+    }
+    elements
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Succeeded {
+        path: PathBuf,
+        size: Option<u64>,
+    },
+    /// Converted, but only after falling back to a worse preset
+    Degraded {
+        path: PathBuf,
+        size: u64,
+        reason: String,
+    },
+    /// Rejected before conversion because it doesn't satisfy the sticker rules
+    Skipped {
+        error: Error,
+    },
+    Failed {
+        error: Error,
+    },
+}
+
+impl Outcome {
+    pub fn from_error(error: Error) -> Outcome {
+        if let Error::Validation { .. } = error {
+            Outcome::Skipped { error }
+        } else {
+            Outcome::Failed { error }
+        }
+    }
+    pub fn status(&self) -> &'static str {
+        match self {
+            Outcome::Succeeded { .. } => "succeeded",
+            Outcome::Degraded { .. } => "degraded",
+            Outcome::Skipped { .. } => "skipped",
+            Outcome::Failed { .. } => "failed",
+        }
+    }
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            Outcome::Succeeded { size, .. } => *size,
+            Outcome::Degraded { size, .. } => Some(*size),
+            Outcome::Failed {
+                error: Error::SizeLimit { size, .. },
+            } => Some(*size),
+            _ => None,
+        }
+    }
+    pub fn stage(&self) -> Option<Stage> {
+        match self {
+            Outcome::Skipped { error } | Outcome::Failed { error } => error.stage(),
+            _ => None,
+        }
+    }
+    pub fn reason(&self) -> Option<String> {
+        match self {
+            Outcome::Succeeded { .. } => None,
+            Outcome::Degraded { reason, .. } => Some(reason.clone()),
+            Outcome::Skipped { error } | Outcome::Failed { error } => Some(error.reason()),
+        }
+    }
+}
+
+/// Collects the outcome of every emote of a run
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub outcomes: Vec<(EmoteId, Outcome)>,
+}
+
+impl Summary {
+    pub fn push(&mut self, id: EmoteId, outcome: Outcome) {
+        self.outcomes.push((id, outcome));
+    }
+    pub fn push_error(&mut self, id: EmoteId, error: Error) {
+        self.push(id, Outcome::from_error(error));
+    }
+    /// Pushes the outcome of every element, mapping successful ones with `f`
+    pub fn extend<T>(
+        &mut self,
+        elements: Vec<BatchElement<T>>,
+        mut f: impl FnMut(EmoteId, T) -> Outcome,
+    ) {
+        for BatchElement { id, result } in elements {
+            match result {
+                Ok(value) => {
+                    let outcome = f(id.clone(), value);
+                    self.push(id, outcome);
+                }
+                Err(err) => {
+                    warn!("couldn't process `{id:?}`: {err}");
+                    self.push_error(id, err);
+                }
+            }
+        }
+    }
+    pub fn count(&self, status: &str) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|(_, outcome)| outcome.status() == status)
+            .count()
+    }
+    pub fn has_failures(&self) -> bool {
+        self.count("failed") > 0
+    }
+    /// `0` if nothing failed, the [`Error::exit_code`] if every failure
+    /// is of the same kind and `1` otherwise
+    pub fn exit_code(&self) -> i32 {
+        let mut codes = self
+            .outcomes
+            .iter()
+            .filter_map(|(_, outcome)| match outcome {
+                Outcome::Failed { error } => Some(error.exit_code()),
+                _ => None,
+            });
+        match codes.next() {
+            None => 0,
+            Some(first) if codes.all(|code| code == first) => first,
+            Some(_) => 1,
+        }
+    }
+    pub fn print(&self) {
+        println!(
+            "{:<10} {:<26} {:<9} {:>9}  REASON",
+            "STATUS", "EMOTE", "STAGE", "SIZE"
+        );
+        for (id, outcome) in &self.outcomes {
+            let stage = outcome.stage().map(|s| s.to_string()).unwrap_or_default();
+            let size = outcome.size().map(|s| s.to_string()).unwrap_or_default();
+            let reason = outcome.reason().unwrap_or_default();
+            println!(
+                "{:<10} {:<26} {:<9} {:>9}  {}",
+                outcome.status(),
+                id.to_string(),
+                stage,
+                size,
+                reason
+            );
+        }
+        println!(
+            "{} succeeded, {} degraded, {} skipped, {} failed",
+            self.count("succeeded"),
+            self.count("degraded"),
+            self.count("skipped"),
+            self.count("failed")
+        );
+    }
+}
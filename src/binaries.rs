@@ -1,15 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
+use std::fmt::Display;
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
+use std::process::Output;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use futures::StreamExt;
+use lazy_regex::regex_captures;
 use log::{error, info, warn};
-use simple_error::simple_error;
 use tokio::process::Command;
 
+use crate::config::{Align, BinaryPaths, Scaler};
 use crate::convert::ConversionOptions;
+use crate::error::{Error, ToolError};
 use crate::webp;
 
 /// Make typing key-value-pair arguments a bit nicer
@@ -22,56 +25,296 @@ impl ArgExt for Command {
     }
 }
 
-async fn run_command(mut cmd: Command) -> Result<()> {
-    let program = cmd.as_std().get_program().to_str().unwrap().to_string();
+async fn run_command(cmd: Command) -> Result<()> {
+    run_command_output(cmd).await.map(|_| ())
+}
+
+async fn run_command_output(mut cmd: Command) -> Result<Output> {
+    let program = cmd.as_std().get_program().to_string_lossy().into_owned();
     let args = cmd
         .as_std()
         .get_args()
         .fold(program.clone(), |mut acc, next| {
             acc.push(' ');
-            acc.push_str(next.to_str().unwrap());
+            acc.push_str(&next.to_string_lossy());
             acc
         });
 
-    let output = cmd.output().await?;
+    let output = cmd.output().await.map_err(|source| ToolError::Spawn {
+        program: program.clone(),
+        source,
+    })?;
 
     if !output.status.success() {
-        let stderr = std::str::from_utf8(&output.stderr).unwrap();
-        error!("process `{program}` fucked up");
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        error!("process `{program}` failed");
         error!("args: `{args}`");
         error!("stderr: `{stderr}`");
-        Err(simple_error!("process `{program}` fucked up").into())
+        Err(ToolError::Failed {
+            program,
+            args,
+            status: output.status,
+            stderr,
+        }
+        .into())
+    } else {
+        Ok(output)
+    }
+}
+
+/// A `major.minor.patch` version as printed by `-version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(pub u32, pub u32, pub u32);
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// How a binary prints its version
+#[derive(Debug, Clone, Copy)]
+enum VersionFormat {
+    /// `1.3.0` or `WebP Encoder version: 1.3.0`
+    Libwebp,
+    /// `ffmpeg version 6.0-full_build-www.gyan.dev`, git builds have no version
+    Ffmpeg,
+    /// `Version: ImageMagick 7.1.0-61 Q8 x64`
+    ImageMagick,
+}
+
+impl VersionFormat {
+    fn parse(self, output: &str) -> Option<Version> {
+        let (major, minor, patch) = match self {
+            VersionFormat::Libwebp => {
+                let (_, major, minor, patch) = regex_captures!(r"(\d+)\.(\d+)\.(\d+)", output)?;
+                (major, minor, patch)
+            }
+            VersionFormat::Ffmpeg => {
+                let (_, major, minor, patch) =
+                    regex_captures!(r"ffmpeg version n?(\d+)\.(\d+)(?:\.(\d+))?", output)?;
+                (major, minor, patch)
+            }
+            VersionFormat::ImageMagick => {
+                let (_, major, minor, patch) =
+                    regex_captures!(r"ImageMagick (\d+)\.(\d+)\.(\d+)", output)?;
+                (major, minor, patch)
+            }
+        };
+        Some(Version(
+            major.parse().ok()?,
+            minor.parse().ok()?,
+            patch.parse().unwrap_or(0),
+        ))
+    }
+}
+
+/// How one of the [`Binaries`] is found and which version it needs
+#[derive(Debug, Clone, Copy)]
+pub struct Tool {
+    pub name: &'static str,
+    /// Environment variable with the path of the binary
+    pub var: &'static str,
+    /// Key of the path in the `[binaries]` section of the config
+    pub key: &'static str,
+    /// Looked up on the `PATH` and in the common install directories
+    pub program: &'static str,
+    /// Only needed by some commands
+    pub optional: bool,
+    format: VersionFormat,
+    /// Oldest version with every feature we use, and the feature that needs it
+    minimum: Option<(Version, &'static str)>,
+}
+
+pub const ANIM_DUMP: Tool = Tool {
+    name: "anim_dump",
+    var: "ANIM_DUMP_BIN",
+    key: "anim_dump",
+    program: "anim_dump",
+    optional: false,
+    format: VersionFormat::Libwebp,
+    minimum: None,
+};
+pub const WEBP_INFO: Tool = Tool {
+    name: "webp_info",
+    var: "WEBP_INFO_BIN",
+    key: "webp_info",
+    program: "webpinfo",
+    optional: false,
+    format: VersionFormat::Libwebp,
+    minimum: None,
+};
+pub const FFMPEG: Tool = Tool {
+    name: "ffmpeg",
+    var: "FFMPEG_BIN",
+    key: "ffmpeg",
+    program: "ffmpeg",
+    optional: false,
+    format: VersionFormat::Ffmpeg,
+    minimum: Some((Version(5, 1, 0), "-fps_mode")),
+};
+pub const MAGICK: Tool = Tool {
+    name: "magick",
+    var: "MAGICK_BIN",
+    key: "magick",
+    program: "magick",
+    optional: true,
+    format: VersionFormat::ImageMagick,
+    minimum: Some((Version(7, 0, 0), "the `magick` command")),
+};
+pub const IMG_2_WEBP: Tool = Tool {
+    name: "img_2_webp",
+    var: "IMG2WEBP_BIN",
+    key: "img2webp",
+    program: "img2webp",
+    optional: false,
+    format: VersionFormat::Libwebp,
+    minimum: Some((Version(1, 0, 0), "-min_size")),
+};
+pub const V_WEBP: Tool = Tool {
+    name: "v_webp",
+    var: "VWEBP_BIN",
+    key: "vwebp",
+    program: "vwebp",
+    optional: true,
+    format: VersionFormat::Libwebp,
+    minimum: None,
+};
+
+/// Where binaries are commonly installed besides the `PATH`
+fn common_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(windows) {
+        // e.g. `C:\Program Files\ImageMagick-7.1.0-Q16\magick.exe` or `[...]\ffmpeg\bin\ffmpeg.exe`
+        let roots = ["ProgramFiles", "ProgramFiles(x86)", "LOCALAPPDATA"]
+            .into_iter()
+            .filter_map(std::env::var_os)
+            .map(PathBuf::from);
+        for root in roots {
+            for entry in crate::list_dir::entries_blocking(&root) {
+                if entry.file_type().is_dir() {
+                    dirs.push(entry.path().join("bin"));
+                    dirs.push(entry.into_path());
+                }
+            }
+        }
     } else {
-        Ok(())
+        dirs.extend(
+            [
+                "/usr/local/bin",
+                "/opt/homebrew/bin",
+                "/opt/local/bin",
+                "/snap/bin",
+            ]
+            .map(PathBuf::from),
+        );
+        dirs.extend(dirs::home_dir().map(|home| home.join(".local").join("bin")));
     }
+    dirs
+}
+
+/// Looks for `program` in the directories of the `PATH`, then in the common install
+/// directories, with the platform's executable suffix, e.g. `ffmpeg.exe` on Windows
+fn find_program(program: &str) -> Option<PathBuf> {
+    let file_name = format!("{program}{}", std::env::consts::EXE_SUFFIX);
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(common_dirs())
+        .map(|dir| dir.join(&file_name))
+        .find(|path| path.is_file())
 }
 
-/// Call the binary with the `-version` argument
-async fn check_version(binary: &Path) -> Result<String> {
-    let output = Command::new(binary).arg("-version").output().await;
-    let name = binary.file_name().unwrap().to_str().unwrap();
+impl Tool {
+    /// Takes the configured path, then the environment variable (also read from `.env`),
+    /// then the `PATH` and the common install directories
+    pub fn locate(&self, configured: Option<&Path>) -> Result<PathBuf, Error> {
+        if let Some(path) = configured {
+            return Ok(path.to_path_buf());
+        }
+        if let Some(path) = std::env::var_os(self.var) {
+            return Ok(path.into());
+        }
+        find_program(self.program).ok_or_else(|| self.missing())
+    }
+    /// Reads the version from the `-version` output and checks it against the minimum
+    fn parse_version(&self, output: &str) -> Result<Option<Version>> {
+        let version = self.format.parse(output);
+        match (version, self.minimum) {
+            (Some(version), Some((minimum, feature))) if version < minimum => {
+                return Err(anyhow!(
+                    "version {version} is older than {minimum}, which `{feature}` needs"
+                ));
+            }
+            (None, Some((minimum, _))) => {
+                warn!(
+                    "couldn't parse the version of `{}`, assuming it's at least {minimum}",
+                    self.name
+                )
+            }
+            _ => {}
+        }
+        Ok(version)
+    }
 
-    match &output {
-        Ok(_) => info!("found binary `{name}`"),
-        Err(err) => error!("couldn't find binary `{binary:?}`: {err}"),
+    fn missing(&self) -> Error {
+        Error::ToolMissing {
+            name: self.name,
+            source: anyhow!(
+                "`{}` isn't set and `{}` isn't on the `PATH`",
+                self.var,
+                self.program
+            ),
+        }
     }
+}
 
-    let stdout = String::from_utf8(output?.stdout)?;
-    Ok(stdout)
+/// What `-version` revealed about a binary
+#[derive(Debug, Clone)]
+pub struct ToolInfo {
+    pub path: PathBuf,
+    /// `None` if the output couldn't be parsed, e.g. for git builds of ffmpeg
+    pub version: Option<Version>,
+    pub output: String,
+}
+
+/// Calls the binary with the `-version` argument and checks the version it prints
+pub async fn check_version(tool: &Tool, binary: &Path) -> Result<ToolInfo> {
+    let output = match Command::new(binary).arg("-version").output().await {
+        Ok(output) => output,
+        Err(err) => {
+            error!("couldn't find binary `{binary:?}`: {err}");
+            return Err(err.into());
+        }
+    };
+    let mut stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    stdout.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    let version = tool.parse_version(&stdout)?;
+    match version {
+        Some(version) => info!("found binary `{}` {version}", tool.name),
+        None => info!("found binary `{}`", tool.name),
+    }
+    Ok(ToolInfo {
+        path: binary.to_path_buf(),
+        version,
+        output: stdout,
+    })
 }
 
 #[derive(Debug)]
 pub struct WebpInfo(PathBuf);
 
 impl WebpInfo {
-    pub fn new(str: &str) -> Self {
-        Self(PathBuf::from_str(str).unwrap())
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
     }
     pub fn path(&self) -> &Path {
         &self.0
     }
     pub async fn info(&self, webp: impl AsRef<Path>) -> Result<webp::WebpInfo> {
-        let output = Command::new(&self.0).arg(webp.as_ref()).output().await?;
+        let mut cmd = Command::new(&self.0);
+        cmd.arg(webp.as_ref());
+        let output = run_command_output(cmd).await?;
         let stdout = String::from_utf8(output.stdout)?;
         webp::WebpInfo::from_stdout(&stdout)
     }
@@ -81,8 +324,8 @@ impl WebpInfo {
 pub struct AnimDump(PathBuf);
 
 impl AnimDump {
-    pub fn new(str: &str) -> Self {
-        Self(PathBuf::from_str(str).unwrap())
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
     }
     pub fn path(&self) -> &Path {
         &self.0
@@ -100,24 +343,69 @@ impl AnimDump {
 pub struct Ffmpeg(PathBuf);
 
 impl Ffmpeg {
-    pub fn new(str: &str) -> Self {
-        Self(PathBuf::from_str(str).unwrap())
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
     }
     pub fn path(&self) -> &Path {
         &self.0
     }
+    /// Whether `ffmpeg -encoders` lists the encoder, e.g. `libwebp`
+    pub async fn has_encoder(&self, encoder: &str) -> Result<bool> {
+        let mut cmd = Command::new(&self.0);
+        cmd.arg("-hide_banner").arg("-encoders");
+        let output = run_command_output(cmd).await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout
+            .lines()
+            .any(|line| line.split_whitespace().nth(1) == Some(encoder)))
+    }
+    /// Union of the bounding boxes of the pixels that are more opaque than `threshold`
+    /// over every image, `None` if every image is fully transparent
+    pub async fn content_bbox(
+        &self,
+        input: impl AsRef<Path>,
+        threshold: u8,
+    ) -> Result<Option<Rect>> {
+        let mut cmd = Command::new(&self.0);
+        cmd.arg("-hide_banner")
+            .arg_pair("-i", input.as_ref())
+            .arg_pair("-vf", format!("alphaextract,bbox=min_val={threshold}"))
+            .arg_pair("-f", "null")
+            .arg("-");
+        let output = run_command_output(cmd).await?;
+        Ok(parse_bbox(&String::from_utf8_lossy(&output.stderr)))
+    }
+    /// Counts the distinct colors of the first `max_frames` images, transparent pixels count once
+    pub async fn count_colors(&self, input: impl AsRef<Path>, max_frames: usize) -> Result<usize> {
+        let mut cmd = Command::new(&self.0);
+        cmd.arg("-hide_banner")
+            .arg_pair("-i", input.as_ref())
+            .arg_pair("-frames:v", max_frames.to_string())
+            .arg_pair("-f", "rawvideo")
+            .arg_pair("-pix_fmt", "rgba")
+            .arg("-");
+        let output = run_command_output(cmd).await?;
+
+        let colors: HashSet<[u8; 4]> = output
+            .stdout
+            .chunks_exact(4)
+            .map(|px| match px {
+                [_, _, _, 0] => [0; 4],
+                _ => [px[0], px[1], px[2], px[3]],
+            })
+            .collect();
+        Ok(colors.len())
+    }
+    /// Fits the images into a transparent `size`x`size` canvas
     pub async fn resize_images(
         &self,
         input: impl AsRef<Path>,
         output: impl AsRef<Path>,
+        resize: &Resize,
     ) -> Result<()> {
-        const VIDEO_FILTER: &str = "\
-            scale=w=512:h=512:force_original_aspect_ratio=decrease,\
-            pad=512:512:-1:-1:color=0x00000000";
-
         let mut cmd = Command::new(&self.0);
         cmd.arg_pair("-i", input.as_ref())
-            .arg_pair("-vf", VIDEO_FILTER)
+            .arg_pair("-vf", resize.video_filter())
             .arg("-y")
             .arg(output.as_ref());
         run_command(cmd).await
@@ -149,6 +437,122 @@ impl Ffmpeg {
     }
 }
 
+/// An area of an image in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Smallest rectangle containing both
+    pub fn union(self, other: Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+}
+
+/// Union of the boxes the `bbox` filter logs, lines that don't parse are skipped
+fn parse_bbox(stderr: &str) -> Option<Rect> {
+    let mut bbox: Option<Rect> = None;
+    for line in stderr.lines() {
+        let Some((_, x1, x2, y1, y2)) =
+            regex_captures!(r"x1:(\d+) x2:(\d+) y1:(\d+) y2:(\d+)", line)
+        else {
+            continue;
+        };
+        let parse = |n: &str| n.parse::<u32>().ok();
+        let (Some(x1), Some(x2), Some(y1), Some(y2)) = (parse(x1), parse(x2), parse(y1), parse(y2))
+        else {
+            continue;
+        };
+        if x2 < x1 || y2 < y1 {
+            continue;
+        }
+        let rect = Rect {
+            x: x1,
+            y: y1,
+            width: x2 - x1 + 1,
+            height: y2 - y1 + 1,
+        };
+        bbox = Some(bbox.map_or(rect, |bbox| bbox.union(rect)));
+    }
+    bbox
+}
+
+/// How [`Ffmpeg::resize_images`] frames the images on the canvas
+#[derive(Debug, Clone)]
+pub struct Resize {
+    /// Width and height of the canvas
+    pub size: i32,
+    /// Cut out before scaling, the same for every frame
+    pub crop: Option<Rect>,
+    /// Empty border between the content and the canvas edges
+    pub margin: u32,
+    pub align: Align,
+    /// `Auto` is resolved by the caller and falls back to lanczos here
+    pub scaler: Scaler,
+    /// Scale premultiplied colors, keeps transparent pixels from darkening the edges
+    pub premultiply: bool,
+}
+
+impl Resize {
+    fn video_filter(&self) -> String {
+        let size = self.size;
+        let inner = size - 2 * self.margin as i32;
+        let mut filter = String::new();
+        if let Some(Rect {
+            x,
+            y,
+            width,
+            height,
+        }) = self.crop
+        {
+            filter.push_str(&format!("crop={width}:{height}:{x}:{y},"));
+        }
+        // negative offsets center the image
+        let y = match self.align {
+            Align::Center => "-1".to_string(),
+            Align::Bottom => format!("oh-ih-{}", self.margin),
+        };
+        let scale = match self.scaler {
+            // whole multiples of the source pixels, shrinking normally if it's too large
+            Scaler::Nearest => {
+                let ratio = format!("min({inner}/iw,{inner}/ih)");
+                let factor = format!("if(gte({ratio},1),floor({ratio}),{ratio})");
+                format!("scale=w='iw*{factor}':h='ih*{factor}':flags=neighbor")
+            }
+            Scaler::Bicubic => {
+                format!("scale=w={inner}:h={inner}:force_original_aspect_ratio=decrease")
+            }
+            Scaler::Auto | Scaler::Lanczos => format!(
+                "scale=w={inner}:h={inner}:force_original_aspect_ratio=decrease:flags=lanczos"
+            ),
+        };
+        if self.premultiply {
+            filter.push_str(&format!(
+                "premultiply=inplace=1,{scale},unpremultiply=inplace=1,"
+            ));
+        } else {
+            filter.push_str(&format!("{scale},"));
+        }
+        filter.push_str(&format!(
+            "pad={size}:{size}:-1:{y}:color=0x00000000,format=rgba"
+        ));
+        filter
+    }
+}
+
 pub struct Img2WebpFrame {
     pub path: PathBuf,
     pub duration: i32,
@@ -175,8 +579,8 @@ impl Img2WebpFrame {
 pub struct Img2Webp(PathBuf);
 
 impl Img2Webp {
-    pub fn new(str: &str) -> Self {
-        Self(PathBuf::from_str(str).unwrap())
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
     }
     pub fn path(&self) -> &Path {
         &self.0
@@ -186,10 +590,11 @@ impl Img2Webp {
         &self,
         output: impl AsRef<Path>,
         frames: &[Img2WebpFrame],
+        loop_count: u32,
     ) -> Result<()> {
         let mut cmd = Command::new(&self.0);
         cmd.arg_pair("-o", output.as_ref())
-            .arg_pair("-loop", "0")
+            .arg_pair("-loop", loop_count.to_string())
             .arg("-min_size");
 
         for frame_opt in frames {
@@ -208,8 +613,8 @@ impl Img2Webp {
 pub struct Magick(PathBuf);
 
 impl Magick {
-    pub fn new(str: &str) -> Self {
-        Self(PathBuf::from_str(str).unwrap())
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
     }
     pub fn path(&self) -> &Path {
         &self.0
@@ -219,27 +624,65 @@ impl Magick {
         input: impl AsRef<Path>,
         output: impl AsRef<Path>,
         lossless: bool,
+        size: i32,
     ) -> Result<()> {
+        let extent = format!("{size}x{size}");
         let mut cmd = Command::new(&self.0);
-        cmd.arg_pair("-size", "512x512")
+        cmd.arg_pair("-size", &extent)
             .arg_pair("-background", "none")
             .arg(input.as_ref())
             .arg_pair("-gravity", "center")
-            .arg_pair("-extent", "512x512");
+            .arg_pair("-extent", &extent);
         if lossless {
             cmd.arg_pair("-define", "webp:lossless=true");
         }
         cmd.arg(output.as_ref());
         run_command(cmd).await
     }
+    /// Whether `magick -list format` lists the format as readable, e.g. `SVG` with its delegate
+    pub async fn can_read(&self, format: &str) -> Result<bool> {
+        let mut cmd = Command::new(&self.0);
+        cmd.arg_pair("-list", "format");
+        let output = run_command_output(cmd).await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout.lines().any(|line| {
+            let mut columns = line.split_whitespace();
+            let name = columns.next().map(|name| name.trim_end_matches('*'));
+            let mode = columns.nth(1);
+            name == Some(format) && mode.is_some_and(|mode| mode.starts_with('r'))
+        }))
+    }
+    /// Converts a GIF, AVIF or PNG to a lossless, possibly animated WebP
+    pub async fn to_webp(&self, input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<()> {
+        let mut cmd = Command::new(&self.0);
+        cmd.arg(input.as_ref())
+            .arg("-coalesce")
+            .arg_pair("-define", "webp:lossless=true")
+            .arg(output.as_ref());
+        run_command(cmd).await
+    }
+    /// Render the first frame of `input` as the 96x96 PNG tray icon of a sticker pack
+    pub async fn tray_icon(&self, input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<()> {
+        let mut input = input.as_ref().as_os_str().to_owned();
+        input.push("[0]");
+
+        let mut cmd = Command::new(&self.0);
+        cmd.arg_pair("-background", "none")
+            .arg(input)
+            .arg_pair("-resize", "96x96")
+            .arg_pair("-gravity", "center")
+            .arg_pair("-extent", "96x96")
+            .arg(output.as_ref());
+        run_command(cmd).await
+    }
 }
 
 #[derive(Debug)]
 pub struct VWebp(PathBuf);
 
 impl VWebp {
-    pub fn new(str: &str) -> Self {
-        Self(PathBuf::from_str(str).unwrap())
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
     }
     pub fn path(&self) -> &Path {
         &self.0
@@ -256,50 +699,284 @@ pub struct Binaries {
     pub anim_dump: AnimDump,
     pub webp_info: WebpInfo,
     pub ffmpeg: Ffmpeg,
-    pub magick: Magick,
     pub img_2_webp: Img2Webp,
-    pub v_webp: VWebp,
+    /// Only needed for static stickers, packs and downloads that aren't WebP
+    magick: Option<Magick>,
+    /// Only needed to preview stickers
+    v_webp: Option<VWebp>,
+}
+
+/// Every tool with the path configured for it
+fn configured(paths: &BinaryPaths) -> [(Tool, Option<&Path>); 6] {
+    [
+        (ANIM_DUMP, paths.anim_dump.as_deref()),
+        (WEBP_INFO, paths.webp_info.as_deref()),
+        (FFMPEG, paths.ffmpeg.as_deref()),
+        (MAGICK, paths.magick.as_deref()),
+        (IMG_2_WEBP, paths.img2webp.as_deref()),
+        (V_WEBP, paths.vwebp.as_deref()),
+    ]
+}
+
+/// Locates every tool without giving up on the first missing one
+pub fn locate_all(paths: &BinaryPaths) -> Vec<(Tool, Result<PathBuf, Error>)> {
+    let _ = dotenv::dotenv();
+    configured(paths)
+        .into_iter()
+        .map(|(tool, path)| (tool, tool.locate(path)))
+        .collect()
 }
 
 impl Binaries {
-    pub fn from_env() -> Result<Self> {
+    /// Locates every binary, see [`Tool::locate`], optional ones may be missing
+    pub fn new(paths: &BinaryPaths) -> Result<Self> {
+        let _ = dotenv::dotenv();
+        let optional = |tool: &Tool, configured: &Option<PathBuf>| {
+            tool.locate(configured.as_deref())
+                .map_err(|err| info!("{err}, it's optional"))
+                .ok()
+        };
         Ok(Self {
-            anim_dump: AnimDump::new(&dotenv::var("ANIM_DUMP_BIN")?),
-            webp_info: WebpInfo::new(&dotenv::var("WEBP_INFO_BIN")?),
-            ffmpeg: Ffmpeg::new(&dotenv::var("FFMPEG_BIN")?),
-            magick: Magick::new(&dotenv::var("MAGICK_BIN")?),
-            img_2_webp: Img2Webp::new(&dotenv::var("IMG2WEBP_BIN")?),
-            v_webp: VWebp::new(&dotenv::var("VWEBP_BIN")?),
+            anim_dump: AnimDump::new(ANIM_DUMP.locate(paths.anim_dump.as_deref())?),
+            webp_info: WebpInfo::new(WEBP_INFO.locate(paths.webp_info.as_deref())?),
+            ffmpeg: Ffmpeg::new(FFMPEG.locate(paths.ffmpeg.as_deref())?),
+            img_2_webp: Img2Webp::new(IMG_2_WEBP.locate(paths.img2webp.as_deref())?),
+            magick: optional(&MAGICK, &paths.magick).map(Magick::new),
+            v_webp: optional(&V_WEBP, &paths.vwebp).map(VWebp::new),
         })
     }
 
-    pub async fn check(&self, parallel: usize) -> Result<HashMap<&'static str, String>> {
-        async fn inner(name: &'static str, path: &Path) -> Result<(&'static str, String)> {
-            Ok((name, check_version(path).await?))
+    pub fn magick(&self) -> Result<&Magick, Error> {
+        self.magick.as_ref().ok_or_else(|| MAGICK.missing())
+    }
+    pub fn v_webp(&self) -> Result<&VWebp, Error> {
+        self.v_webp.as_ref().ok_or_else(|| V_WEBP.missing())
+    }
+
+    /// Every tool with the path it was found at, `None` for missing optional ones
+    pub fn tools(&self) -> Vec<(Tool, Option<&Path>)> {
+        vec![
+            (ANIM_DUMP, Some(self.anim_dump.path())),
+            (WEBP_INFO, Some(self.webp_info.path())),
+            (FFMPEG, Some(self.ffmpeg.path())),
+            (MAGICK, self.magick.as_ref().map(Magick::path)),
+            (IMG_2_WEBP, Some(self.img_2_webp.path())),
+            (V_WEBP, self.v_webp.as_ref().map(VWebp::path)),
+        ]
+    }
+
+    /// Calls every binary with `-version`, keeping every result
+    pub async fn check_each(&self, parallel: usize) -> Vec<(Tool, Result<ToolInfo>)> {
+        async fn inner(tool: Tool, path: Option<&Path>) -> (Tool, Result<ToolInfo>) {
+            let result = match path {
+                Some(path) => check_version(&tool, path).await,
+                None => Err(tool.missing().into()),
+            };
+            (tool, result)
         }
 
-        let to_check = [
-            inner("anim_dump", self.anim_dump.path()),
-            inner("webp_info", self.webp_info.path()),
-            inner("ffmpeg", self.ffmpeg.path()),
-            inner("magick", self.magick.path()),
-            inner("img_2_webp", self.img_2_webp.path()),
-            inner("v_webp", self.v_webp.path()),
-        ];
-
-        let mut map = HashMap::with_capacity(to_check.len());
-        let results = futures::stream::iter(to_check)
+        futures::stream::iter(self.tools())
+            .map(|(tool, path)| inner(tool, path))
             .buffer_unordered(parallel)
             .collect::<Vec<_>>()
-            .await;
-
-        for result in results {
+            .await
+    }
+    /// Fails if a required binary or one of the optional ones in `needed` is missing or too old
+    pub async fn check(
+        &self,
+        parallel: usize,
+        needed: &[&str],
+    ) -> Result<HashMap<&'static str, ToolInfo>> {
+        let results = self.check_each(parallel).await;
+        let mut map = HashMap::with_capacity(results.len());
+        for (tool, result) in results {
             match result {
-                Ok((name, val)) => map.insert(name, val),
-                Err(err) => return Err(err),
-            };
+                Ok(info) => {
+                    map.insert(tool.name, info);
+                }
+                Err(_) if tool.optional && !needed.contains(&tool.name) => {}
+                Err(source) => {
+                    let name = tool.name;
+                    return Err(Error::ToolMissing { name, source }.into());
+                }
+            }
         }
-
         Ok(map)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bbox_of_every_frame() {
+        let stderr = "\
+            [Parsed_bbox_1 @ 0x1] n:0 pts:0 pts_time:0 x1:10 x2:19 y1:4 y2:8 w:10 h:5\n\
+            frame=    1 fps=0.0 q=-0.0 size=N/A time=00:00:00.04\n\
+            [Parsed_bbox_1 @ 0x1] n:1 pts:1 pts_time:0.04 x1:6 x2:12 y1:7 y2:20 w:7 h:14\n";
+        let bbox = Rect {
+            x: 6,
+            y: 4,
+            width: 14,
+            height: 17,
+        };
+        assert_eq!(parse_bbox(stderr), Some(bbox));
+        assert_eq!(parse_bbox("frame=    1 fps=0.0 q=-0.0 size=N/A"), None);
+    }
+
+    #[test]
+    fn bbox_skips_unparsable_lines() {
+        // inverted boxes would underflow, too large numbers don't fit
+        let stderr = "\
+            [Parsed_bbox_1 @ 0x1] n:0 x1:20 x2:10 y1:0 y2:5\n\
+            [Parsed_bbox_1 @ 0x1] n:1 x1:99999999999 x2:5 y1:0 y2:5\n\
+            [Parsed_bbox_1 @ 0x1] n:2 x1:1 x2:2 y1:3 y2:4\n";
+        let bbox = Rect {
+            x: 1,
+            y: 3,
+            width: 2,
+            height: 2,
+        };
+        assert_eq!(parse_bbox(stderr), Some(bbox));
+    }
+
+    #[test]
+    fn union_of_rects() {
+        let a = Rect {
+            x: 2,
+            y: 10,
+            width: 4,
+            height: 4,
+        };
+        let b = Rect {
+            x: 8,
+            y: 1,
+            width: 2,
+            height: 3,
+        };
+        let union = Rect {
+            x: 2,
+            y: 1,
+            width: 8,
+            height: 13,
+        };
+        assert_eq!(a.union(b), union);
+        assert_eq!(b.union(a), union);
+        assert_eq!(a.union(a), a);
+    }
+
+    fn resize() -> Resize {
+        Resize {
+            size: 512,
+            crop: None,
+            margin: 0,
+            align: Align::Center,
+            scaler: Scaler::Lanczos,
+            premultiply: false,
+        }
+    }
+
+    #[test]
+    fn resize_filter() {
+        assert_eq!(
+            resize().video_filter(),
+            "scale=w=512:h=512:force_original_aspect_ratio=decrease:flags=lanczos,\
+            pad=512:512:-1:-1:color=0x00000000,format=rgba"
+        );
+    }
+
+    #[test]
+    fn resize_filter_crops_and_keeps_a_margin_at_the_bottom() {
+        let resize = Resize {
+            crop: Some(Rect {
+                x: 3,
+                y: 5,
+                width: 100,
+                height: 80,
+            }),
+            margin: 16,
+            align: Align::Bottom,
+            ..resize()
+        };
+        assert_eq!(
+            resize.video_filter(),
+            "crop=100:80:3:5,\
+            scale=w=480:h=480:force_original_aspect_ratio=decrease:flags=lanczos,\
+            pad=512:512:-1:oh-ih-16:color=0x00000000,format=rgba"
+        );
+    }
+
+    #[test]
+    fn resize_filter_premultiplies() {
+        let resize = Resize {
+            premultiply: true,
+            scaler: Scaler::Nearest,
+            ..resize()
+        };
+        let filter = resize.video_filter();
+        assert!(
+            filter.starts_with("premultiply=inplace=1,scale=w='iw*if(gte(min(512/iw,512/ih),1)")
+        );
+        assert!(filter.contains(":flags=neighbor,unpremultiply=inplace=1,pad="));
+    }
+
+    #[test]
+    fn ffmpeg_versions() {
+        let release = "ffmpeg version 6.0-full_build-www.gyan.dev Copyright (c) 2000-2023 \
+            the FFmpeg developers\nbuilt with gcc 12.2.0 (Rev10, Built by MSYS2 project)";
+        assert_eq!(
+            FFMPEG.parse_version(release).unwrap(),
+            Some(Version(6, 0, 0))
+        );
+
+        let tag = "ffmpeg version n6.1 Copyright (c) 2000-2023 the FFmpeg developers";
+        assert_eq!(FFMPEG.parse_version(tag).unwrap(), Some(Version(6, 1, 0)));
+
+        // git builds only have a commit count, they're assumed to be new enough
+        let git = "ffmpeg version N-109990-g1b1f6e8f8e-20230301 Copyright (c) 2000-2023 \
+            the FFmpeg developers";
+        assert_eq!(FFMPEG.parse_version(git).unwrap(), None);
+    }
+
+    #[test]
+    fn old_ffmpeg_is_rejected() {
+        let ubuntu = "ffmpeg version 4.4.2-0ubuntu0.22.04.1 Copyright (c) 2000-2021 \
+            the FFmpeg developers";
+        assert_eq!(FFMPEG.format.parse(ubuntu), Some(Version(4, 4, 2)));
+        let err = FFMPEG.parse_version(ubuntu).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "version 4.4.2 is older than 5.1.0, which `-fps_mode` needs"
+        );
+    }
+
+    #[test]
+    fn imagemagick_version() {
+        let output =
+            "Version: ImageMagick 7.1.0-61 Q16-HDRI x86_64 20994 https://imagemagick.org\n\
+            Copyright: (C) 1999 ImageMagick Studio LLC";
+        assert_eq!(
+            MAGICK.parse_version(output).unwrap(),
+            Some(Version(7, 1, 0))
+        );
+
+        let legacy = "Version: ImageMagick 6.9.11-60 Q16 x86_64 2021-01-25 https://imagemagick.org";
+        assert!(MAGICK.parse_version(legacy).is_err());
+    }
+
+    #[test]
+    fn libwebp_versions() {
+        assert_eq!(
+            WEBP_INFO.parse_version("1.3.0\n").unwrap(),
+            Some(Version(1, 3, 0))
+        );
+
+        let img2webp = "WebP Encoder version: 1.3.0\nWebP Mux version: 1.3.0\n";
+        assert_eq!(
+            IMG_2_WEBP.parse_version(img2webp).unwrap(),
+            Some(Version(1, 3, 0))
+        );
+        assert!(IMG_2_WEBP.parse_version("0.6.1\n").is_err());
+    }
+}
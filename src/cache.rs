@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::download::Validators;
+use crate::source::Rendition;
+
+/// Stored next to every download to revalidate it with a conditional request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub url: String,
+    pub rendition: Rendition,
+    pub validators: Validators,
+    /// SHA-256 of the downloaded bytes, before any conversion to WebP
+    pub sha256: String,
+    pub fetched: DateTime<Utc>,
+    /// Looked up once so later runs and offline ones know it too
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// `dl/<id>.webp` is described by `dl/<id>.webp.http.json`
+pub fn entry_path(download: &Path) -> PathBuf {
+    let mut file_name = download.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".http.json");
+    download.with_file_name(file_name)
+}
+
+/// `frames/<id>` is described by `frames/<id>.json`, the frames are made from the download
+/// and this records the settings they were made with
+pub fn stamp_path(dir: &Path) -> PathBuf {
+    let mut file_name = dir.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".json");
+    dir.with_file_name(file_name)
+}
+
+/// The stamp of a directory of frames, if there is a readable one
+pub async fn read_stamp<T: DeserializeOwned>(dir: &Path) -> Option<T> {
+    let data = tokio::fs::read(stamp_path(dir)).await.ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+pub async fn write_stamp<T: Serialize>(dir: &Path, stamp: &T) -> Result<()> {
+    let data = serde_json::to_vec_pretty(stamp)?;
+    Ok(tokio::fs::write(stamp_path(dir), data).await?)
+}
+
+/// The entry of a download, if there is a readable one
+pub async fn read(download: &Path) -> Option<CacheEntry> {
+    let data = tokio::fs::read(entry_path(download)).await.ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+pub async fn write(download: &Path, entry: &CacheEntry) -> Result<()> {
+    let data = serde_json::to_vec_pretty(entry)?;
+    Ok(tokio::fs::write(entry_path(download), data).await?)
+}
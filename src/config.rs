@@ -0,0 +1,668 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context as _, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use toml::Value;
+
+use crate::emote::{self, Preset};
+use crate::source::Format;
+
+/// Name of the configuration file, looked up in the working directory
+/// and in the user's configuration directory
+pub const FILE_NAME: &str = "convertoid.toml";
+
+/// Profiles that are always available, the configuration files can extend them
+const BUILTIN_PROFILES: &str = r#"
+[profile.whatsapp]
+
+[profile.hq]
+encoder.presets = [
+    { quality = 95, level = 6 },
+    { quality = 85, level = 6 },
+    { quality = 75, level = 6 },
+    { quality = 50, level = 6 },
+]
+output.static_lossless = true
+"#;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Dirs {
+    pub download: PathBuf,
+    pub raw_frames: PathBuf,
+    pub resized_frames: PathBuf,
+    pub out_static: PathBuf,
+    pub out_anim: PathBuf,
+}
+
+impl Default for Dirs {
+    fn default() -> Self {
+        Dirs {
+            download: "./dl/".into(),
+            raw_frames: "./frames-raw/".into(),
+            resized_frames: "./frames-resized/".into(),
+            out_static: "./out-static/".into(),
+            out_anim: "./out-anim/".into(),
+        }
+    }
+}
+
+/// Paths of the external binaries, unset ones are taken from the environment
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BinaryPaths {
+    pub anim_dump: Option<PathBuf>,
+    pub webp_info: Option<PathBuf>,
+    pub ffmpeg: Option<PathBuf>,
+    pub magick: Option<PathBuf>,
+    pub img2webp: Option<PathBuf>,
+    pub vwebp: Option<PathBuf>,
+}
+
+/// How many emotes are processed at the same time
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Concurrency {
+    /// Downloading, probing, extracting and resizing
+    pub process: usize,
+    /// Encoding the stickers
+    pub encode: usize,
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Concurrency {
+            process: 5,
+            encode: 14,
+        }
+    }
+}
+
+/// Behaviour of the HTTP client
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Http {
+    /// Timeout of a single request, including reading the body
+    pub timeout_secs: u64,
+    /// How often failed requests are retried on timeouts, 5xx and 429 responses
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further one
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Requests that may be in flight per host at the same time
+    pub per_host_concurrency: usize,
+    /// Minimum time between the start of two requests to the same host
+    pub per_host_interval_ms: u64,
+    /// Responses with larger bodies are aborted
+    pub max_body_size: u64,
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Http {
+            timeout_secs: 30,
+            retries: 3,
+            backoff_ms: 500,
+            max_backoff_ms: 10_000,
+            per_host_concurrency: 4,
+            per_host_interval_ms: 50,
+            max_body_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Which rendition of an emote is downloaded
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Download {
+    /// Accepted formats, in order of preference
+    pub formats: Vec<Format>,
+    /// How much a rendition may be scaled up to reach the sticker size
+    pub max_upscale: f64,
+}
+
+impl Default for Download {
+    fn default() -> Self {
+        Download {
+            formats: vec![Format::Webp, Format::Gif, Format::Avif, Format::Png],
+            max_upscale: 4.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Encoder {
+    /// Tried in order until the animated sticker fits into the size budget
+    pub presets: Vec<Preset>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Encoder {
+            presets: emote::QUALITY_PRESETS.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub static_size: u64,
+    pub animated_size: u64,
+    pub min_frame_duration_ms: i32,
+    pub max_total_duration_ms: i32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            static_size: emote::STATIC_SIZE_LIMIT,
+            animated_size: emote::ANIMATED_SIZE_LIMIT,
+            min_frame_duration_ms: emote::ANIMATED_MIN_FRAME_DURATION_MS,
+            max_total_duration_ms: emote::ANIMATED_MAX_TOTAL_DURATION_MS,
+        }
+    }
+}
+
+/// What to do with animated emotes whose frame durations break the limits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetimePolicy {
+    /// Fail the emote
+    #[default]
+    Reject,
+    /// Lengthen too short frames and speed up too long emotes
+    Clamp,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retiming {
+    pub policy: RetimePolicy,
+}
+
+/// Movement that makes an animated sticker out of a static emote
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Motion {
+    Shake,
+    Spin,
+    Bounce,
+    Pulse,
+    /// Cycles through the hues
+    Rainbow,
+    /// Zooms into the center, like the meme
+    Zoom,
+}
+
+impl FromStr for Motion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "shake" => Ok(Motion::Shake),
+            "spin" => Ok(Motion::Spin),
+            "bounce" => Ok(Motion::Bounce),
+            "pulse" => Ok(Motion::Pulse),
+            "rainbow" => Ok(Motion::Rainbow),
+            "zoom" => Ok(Motion::Zoom),
+            _ => Err(anyhow!(
+                "unknown motion `{s}`, expected `shake`, `spin`, `bounce`, `pulse`, `rainbow` or `zoom`"
+            )),
+        }
+    }
+}
+
+/// Changes to the frames of animated emotes, applied in the order of the fields
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Animation {
+    /// First and last frame to keep, counting from 0
+    pub range: Option<(usize, usize)>,
+    pub reverse: bool,
+    /// Play the frames forwards and then backwards
+    pub boomerang: bool,
+    /// Playback speed, `2.0` halves every duration
+    pub speed: f64,
+    /// How often the sticker plays, `0` loops forever
+    pub loop_count: u32,
+    /// Animate static emotes, the transforms above apply to the generated frames
+    pub motion: Option<Motion>,
+    /// Number of frames generated for `motion`
+    pub motion_frames: usize,
+    /// Length of one cycle of `motion`
+    pub motion_duration_ms: i32,
+    /// Replacements for single emotes, keyed by `<source>:<id>`
+    pub emotes: HashMap<String, Animation>,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Animation {
+            range: None,
+            reverse: false,
+            boomerang: false,
+            speed: 1.0,
+            loop_count: 0,
+            motion: None,
+            motion_frames: 24,
+            motion_duration_ms: 1200,
+            emotes: HashMap::new(),
+        }
+    }
+}
+
+impl Animation {
+    /// The transforms of the emote, given as `<source>:<id>`
+    pub fn for_emote(&self, emote: &str) -> &Animation {
+        self.emotes.get(emote).unwrap_or(self)
+    }
+    fn validate(&self, key: &str) -> Result<()> {
+        if !(self.speed > 0.0 && self.speed.is_finite()) {
+            return Err(anyhow!("`{key}.speed` must be positive"));
+        }
+        if self.motion_frames < 2 || self.motion_duration_ms <= 0 {
+            return Err(anyhow!(
+                "`{key}` needs at least 2 `motion_frames` and a positive `motion_duration_ms`"
+            ));
+        }
+        for (emote, animation) in &self.emotes {
+            if !animation.emotes.is_empty() {
+                return Err(anyhow!(
+                    "`{key}.emotes.\"{emote}\"` can't have emotes itself"
+                ));
+            }
+            animation.validate(&format!("{key}.emotes.\"{emote}\""))?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the content sits on the sticker canvas
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    Center,
+    Bottom,
+}
+
+/// How the content of the frames is placed on the sticker canvas
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Framing {
+    /// Crop every frame to the union of the non-transparent areas of all frames
+    pub trim: bool,
+    /// Alpha values up to this count as transparent when trimming
+    pub alpha_threshold: u8,
+    /// Empty border around the content, in pixels of the sticker
+    pub margin: u32,
+    pub align: Align,
+}
+
+/// RGBA color, written as `#rrggbb` or `#rrggbbaa`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color(pub [u8; 4]);
+
+impl From<Color> for String {
+    fn from(Color([r, g, b, a]): Color) -> String {
+        format!("#{r:02x}{g:02x}{b:02x}{a:02x}")
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        let hex = value
+            .strip_prefix('#')
+            .filter(|hex| matches!(hex.len(), 6 | 8) && hex.is_ascii())
+            .ok_or_else(|| anyhow!("invalid color `{value}`, expected `#rrggbb` or `#rrggbbaa`"))?;
+        let mut rgba = [255; 4];
+        for (channel, index) in rgba.iter_mut().zip((0..hex.len()).step_by(2)) {
+            *channel = u8::from_str_radix(&hex[index..index + 2], 16)
+                .map_err(|_| anyhow!("invalid color `{value}`"))?;
+        }
+        Ok(Color(rgba))
+    }
+}
+
+/// Decorations drawn around the content of every frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Effects {
+    /// Width of the outline in pixels of the sticker, `0` disables it
+    pub stroke_width: u32,
+    pub stroke_color: Color,
+    pub shadow: bool,
+    pub shadow_color: Color,
+    /// Horizontal and vertical offset of the shadow
+    pub shadow_offset: (i32, i32),
+    /// Radius of the shadow's blur
+    pub shadow_blur: u32,
+    /// Fill of the whole canvas behind everything else
+    pub background: Option<Color>,
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Effects {
+            stroke_width: 0,
+            stroke_color: Color([255, 255, 255, 255]),
+            shadow: false,
+            shadow_color: Color([0, 0, 0, 128]),
+            shadow_offset: (4, 4),
+            shadow_blur: 4,
+            background: None,
+        }
+    }
+}
+
+impl Effects {
+    pub fn is_enabled(&self) -> bool {
+        self.stroke_width > 0 || self.shadow || self.background.is_some()
+    }
+    /// How far the effects reach beyond the content, the content is shrunk by this much
+    pub fn extent(&self) -> u32 {
+        let shadow = if self.shadow {
+            let (x, y) = self.shadow_offset;
+            x.unsigned_abs().max(y.unsigned_abs()) + self.shadow_blur
+        } else {
+            0
+        };
+        self.stroke_width + shadow
+    }
+}
+
+/// Where the caption sits on the sticker
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptionPosition {
+    Top,
+    #[default]
+    Bottom,
+}
+
+/// Text drawn over every frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Caption {
+    pub enabled: bool,
+    /// Text of every sticker, the name of the emote if missing
+    pub text: Option<String>,
+    /// TrueType or OpenType font file
+    pub font: Option<PathBuf>,
+    /// Font size in pixels of the sticker
+    pub size: f32,
+    pub color: Color,
+    /// Width of the outline of the letters, `0` disables it
+    pub stroke_width: u32,
+    pub stroke_color: Color,
+    pub position: CaptionPosition,
+    /// Shrink the text until it fits the width of the sticker
+    pub fit: bool,
+}
+
+impl Default for Caption {
+    fn default() -> Self {
+        Caption {
+            enabled: false,
+            text: None,
+            font: None,
+            size: 72.0,
+            color: Color([255, 255, 255, 255]),
+            stroke_width: 4,
+            stroke_color: Color([0, 0, 0, 255]),
+            position: CaptionPosition::Bottom,
+            fit: true,
+        }
+    }
+}
+
+/// How the transparent edges of the frames are prepared for lossy encoding
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlphaCleanup {
+    /// Resize with premultiplied alpha so transparent colors don't darken the edges
+    pub premultiply: bool,
+    /// Give transparent pixels the colors of their visible neighbours
+    pub bleed: bool,
+    /// Alpha values up to this become transparent, the rest opaque
+    pub threshold: Option<u8>,
+}
+
+impl Default for AlphaCleanup {
+    fn default() -> Self {
+        AlphaCleanup {
+            premultiply: true,
+            bleed: true,
+            threshold: None,
+        }
+    }
+}
+
+/// How the frames are resampled to the sticker size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scaler {
+    /// `nearest` for pixel art, `lanczos` for everything else
+    #[default]
+    Auto,
+    /// Integer nearest-neighbour upscaling, keeps pixel art crisp
+    Nearest,
+    Lanczos,
+    Bicubic,
+}
+
+impl FromStr for Scaler {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Scaler::Auto),
+            "nearest" => Ok(Scaler::Nearest),
+            "lanczos" => Ok(Scaler::Lanczos),
+            "bicubic" => Ok(Scaler::Bicubic),
+            _ => Err(anyhow!(
+                "unknown scaler `{s}`, expected `auto`, `nearest`, `lanczos` or `bicubic`"
+            )),
+        }
+    }
+}
+
+/// Which resampling is used and when emotes count as pixel art
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scaling {
+    pub scaler: Scaler,
+    /// Emotes up to this width and height can be pixel art
+    pub pixel_art_max_size: i32,
+    /// Emotes with up to this many distinct colors can be pixel art
+    pub pixel_art_max_colors: usize,
+}
+
+impl Default for Scaling {
+    fn default() -> Self {
+        Scaling {
+            scaler: Scaler::Auto,
+            pixel_art_max_size: 128,
+            pixel_art_max_colors: 64,
+        }
+    }
+}
+
+/// What the stickers look like
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Output {
+    /// Width and height of the square sticker canvas
+    pub size: i32,
+    /// Encode static stickers losslessly
+    pub static_lossless: bool,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            size: emote::STICKER_SIZE.0,
+            static_lossless: false,
+        }
+    }
+}
+
+/// Every tunable, after merging the configuration files, the profile and the CLI flags
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub dirs: Dirs,
+    pub binaries: BinaryPaths,
+    pub concurrency: Concurrency,
+    pub http: Http,
+    pub download: Download,
+    pub encoder: Encoder,
+    pub limits: Limits,
+    pub retiming: Retiming,
+    pub animation: Animation,
+    pub framing: Framing,
+    pub scaling: Scaling,
+    pub effects: Effects,
+    pub caption: Caption,
+    pub alpha: AlphaCleanup,
+    pub output: Output,
+}
+
+/// Recursively merges `other` into `base`, values of `other` take precedence
+fn merge(base: &mut Value, other: Value) {
+    match (base, other) {
+        (Value::Table(base), Value::Table(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+fn read_file(path: &Path) -> Result<Option<Value>> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("couldn't read `{path:?}`")),
+    };
+    let value = toml::from_str(&data).map_err(|err| anyhow!("invalid config `{path:?}`: {err}"))?;
+    info!("loaded config `{path:?}`");
+    Ok(Some(value))
+}
+
+/// User-level, project-level and explicitly given configuration files, in that order
+fn config_files(file: Option<&Path>) -> Vec<PathBuf> {
+    let mut files = Vec::with_capacity(3);
+    if let Some(dir) = dirs::config_dir() {
+        files.push(dir.join("convertoid").join(FILE_NAME));
+    }
+    files.push(PathBuf::from(FILE_NAME));
+    files.extend(file.map(Path::to_path_buf));
+    files
+}
+
+impl Settings {
+    /// Loads the configuration files, including `file` if given, and applies the profile
+    pub fn load(profile: Option<&str>, file: Option<&Path>) -> Result<Settings> {
+        let mut merged = toml::from_str::<Value>(BUILTIN_PROFILES)?;
+        for path in config_files(file) {
+            match read_file(&path)? {
+                Some(value) => merge(&mut merged, value),
+                None if file == Some(path.as_path()) => {
+                    return Err(anyhow!("config `{path:?}` doesn't exist"))
+                }
+                None => {}
+            }
+        }
+
+        let table = merged.as_table_mut().unwrap();
+        let mut profiles = match table.remove("profile") {
+            Some(Value::Table(profiles)) => profiles,
+            _ => Default::default(),
+        };
+        if let Some(name) = profile {
+            let profile = profiles.remove(name).ok_or_else(|| {
+                let known = profiles.keys().cloned().collect::<Vec<_>>().join(", ");
+                anyhow!("unknown profile `{name}`, known profiles: {known}")
+            })?;
+            merge(&mut merged, profile);
+            info!("using profile `{name}`");
+        }
+
+        let settings: Settings = merged
+            .try_into()
+            .map_err(|err| anyhow!("invalid configuration: {err}"))?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Checks the values that serde can't, run it again after changing the settings
+    pub fn validate(&self) -> Result<()> {
+        if self.encoder.presets.is_empty() {
+            return Err(anyhow!("`encoder.presets` must not be empty"));
+        }
+        if self.concurrency.process == 0 || self.concurrency.encode == 0 {
+            return Err(anyhow!("`concurrency` values must be at least 1"));
+        }
+        if self.http.per_host_concurrency == 0 {
+            return Err(anyhow!("`http.per_host_concurrency` must be at least 1"));
+        }
+        if self.http.max_body_size == 0 {
+            return Err(anyhow!("`http.max_body_size` must be at least 1"));
+        }
+        if self.download.formats.is_empty() {
+            return Err(anyhow!("`download.formats` must not be empty"));
+        }
+        if self.output.size <= 0 {
+            return Err(anyhow!("`output.size` must be positive"));
+        }
+        self.animation.validate("animation")?;
+        if self.caption.enabled && self.caption.font.is_none() {
+            return Err(anyhow!("`caption.font` must be set to draw captions"));
+        }
+        if self.caption.size <= 0.0 {
+            return Err(anyhow!("`caption.size` must be positive"));
+        }
+        let margin = self.framing.margin as i64 + self.effects.extent() as i64;
+        if 2 * margin >= self.output.size as i64 {
+            return Err(anyhow!(
+                "`framing.margin` and the reach of the `effects` must be less than half of `output.size`"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Creates the directories that don't exist yet
+    pub fn create_dirs(&self) -> Result<()> {
+        let dirs = &self.dirs;
+        for dir in [
+            &dirs.download,
+            &dirs.raw_frames,
+            &dirs.resized_frames,
+            &dirs.out_static,
+            &dirs.out_anim,
+        ] {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| anyhow!("couldn't create directory `{dir:?}`: {err}"))?;
+        }
+        Ok(())
+    }
+
+    pub fn sticker_size(&self) -> (i32, i32) {
+        (self.output.size, self.output.size)
+    }
+}
@@ -1,101 +1,460 @@
-use anyhow::Result;
-use futures::StreamExt;
-use log::info;
-use structopt::StructOpt;
+use anyhow::{anyhow, Result};
+use derive_builder::Builder;
+use log::{info, warn};
 use walkdir::WalkDir;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::batch::{self, BatchElement};
 use crate::binaries::Binaries;
-use crate::download::Client;
-use crate::emote_ext::{BttvId, EmoteId, EmoteIdExt, SevenTvId};
-use crate::opt::Opt;
+use crate::cache::{self, CacheEntry};
+use crate::config::Settings;
+use crate::download::{Body, BodyWriter, Client, Fetched, Validators};
+use crate::emote_ext::EmoteId;
+use crate::error::{Error, Stage};
+use crate::progress::Progress;
+use crate::report::Recorder;
+use crate::source::{self, EmoteSource, Rendition, Sources};
 use crate::webp;
 
-#[derive(Debug, Clone)]
+/// Everything the pipeline needs, independent of the command line.
+///
+/// Build it with [`ContextBuilder`], every field has a default.
+#[derive(Debug, Clone, Builder)]
+#[builder(build_fn(error = "anyhow::Error"))]
 pub struct Context {
-    pub opt: Arc<Opt>,
+    #[builder(default, setter(into))]
+    pub settings: Arc<Settings>,
+    /// Configured by the settings
+    #[builder(default = "self.default_client()")]
     pub client: Client,
+    /// Providers the emotes are taken from
+    #[builder(default, setter(into))]
+    pub sources: Arc<Sources>,
+    /// Taken from the settings, falling back to the environment
+    #[builder(default = "self.default_bin()?", setter(into))]
     pub bin: Arc<Binaries>,
+    #[builder(default)]
+    pub recorder: Arc<Recorder>,
+    #[builder(default)]
+    pub progress: Progress,
+    /// Stop at the first emote that fails instead of processing the rest
+    #[builder(default)]
+    pub fail_fast: bool,
+    /// Revalidate existing downloads instead of reusing them
+    #[builder(default)]
+    pub refresh: bool,
+    /// Only use what's in the download directory, never touch the network
+    #[builder(default)]
+    pub offline: bool,
+}
+
+impl ContextBuilder {
+    fn default_client(&self) -> Client {
+        let settings = self.settings.clone().unwrap_or_default();
+        let client = Client::new(&settings.http);
+        match self.offline {
+            Some(true) => client.offline(),
+            _ => client,
+        }
+    }
+    fn default_bin(&self) -> Result<Arc<Binaries>> {
+        let settings = self.settings.clone().unwrap_or_default();
+        Ok(Arc::new(Binaries::new(&settings.binaries)?))
+    }
 }
 
 impl Context {
-    pub fn new() -> Result<Context> {
-        Ok(Context {
-            opt: Arc::new(Opt::from_args()),
-            client: Client::new(),
-            bin: Arc::new(Binaries::from_env()?),
-        })
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder::default()
     }
 
-    pub fn as_seven_tv_ids(&self) -> &[SevenTvId] {
-        &self.opt.seven_tv_ids
+    /// Records that `stage` is done for the emote and advances its progress bar
+    pub fn finish_stage(&self, id: &EmoteId, stage: Stage, executed: bool, elapsed: Duration) {
+        self.recorder.stage(id, stage, executed, elapsed);
+        self.progress.inc(stage);
     }
-    pub fn as_bttv_ids(&self) -> &[BttvId] {
-        &self.opt.bttv_ids
+
+    pub fn download_path(&self, id: &EmoteId) -> PathBuf {
+        self.settings.dirs.download.join(self.sources.file_name(id))
     }
-    pub fn to_emote_ids(&self) -> Vec<EmoteId> {
-        let bttv_ids = self.as_bttv_ids();
-        let seven_tv_ids = self.as_seven_tv_ids();
-        let mut ids = Vec::with_capacity(bttv_ids.len() + seven_tv_ids.len());
-        ids.extend(bttv_ids.iter().map(EmoteId::from));
-        ids.extend(seven_tv_ids.iter().map(EmoteId::from));
-        ids
+    /// Where a download is streamed to before its format is known
+    fn part_path(&self, id: &EmoteId) -> PathBuf {
+        self.settings
+            .dirs
+            .download
+            .join(format!("{}.part", id.key()))
     }
-
-    pub fn download_path(&self, id: EmoteId) -> PathBuf {
-        self.opt.download_dir.join(id.to_file_name())
+    pub fn raw_frames_path(&self, id: &EmoteId) -> PathBuf {
+        self.settings.dirs.raw_frames.join(id.key())
     }
-    pub fn raw_frames_path(&self, id: EmoteId) -> PathBuf {
-        self.opt.raw_frames_dir.join(id.to_string())
+    pub fn resized_frames_path(&self, id: &EmoteId) -> PathBuf {
+        self.settings.dirs.resized_frames.join(id.key())
     }
-    pub fn resized_frames_path(&self, id: EmoteId) -> PathBuf {
-        self.opt.resized_frames_dir.join(id.to_string())
+    /// Frames generated from a static emote by a motion preset
+    pub fn motion_frames_path(&self, id: &EmoteId) -> PathBuf {
+        self.settings
+            .dirs
+            .resized_frames
+            .join(format!("{}.motion", id.key()))
     }
-    pub fn static_out_path(&self, id: EmoteId) -> PathBuf {
-        self.opt.out_static_dir.join(id.to_file_name())
+    pub fn static_out_path(&self, id: &EmoteId) -> PathBuf {
+        self.settings
+            .dirs
+            .out_static
+            .join(format!("{}.webp", id.key()))
     }
-    pub fn anim_out_path(&self, id: EmoteId) -> PathBuf {
-        self.opt.out_anim_dir.join(id.to_file_name())
+    pub fn anim_out_path(&self, id: &EmoteId) -> PathBuf {
+        self.settings
+            .dirs
+            .out_anim
+            .join(format!("{}.webp", id.key()))
     }
 
-    pub async fn download_emote(&self, id: EmoteId) -> Result<()> {
-        let dl = self.client.get_emote(id).await?;
-        dl.write_to(&self.opt.download_dir).await?;
-        info!("downloaded emote `{id:?}`");
+    /// The source of the emote and its renditions in the order they're tried
+    pub fn renditions(&self, id: &EmoteId) -> Result<(&dyn EmoteSource, Vec<Rendition>)> {
+        let source = self.sources.get(id.source())?;
+        let settings = &self.settings;
+        let renditions = source::negotiate(
+            &source.renditions(),
+            &settings.download.formats,
+            settings.output.size as u32,
+            settings.download.max_upscale,
+        );
+        Ok((source, renditions))
+    }
+
+    /// Downloads the first rendition of the emote that's available,
+    /// converting it to WebP if necessary
+    pub async fn fetch_emote(&self, id: &EmoteId) -> Result<Rendition> {
+        if self.offline {
+            return Err(anyhow!(
+                "`{:?}` isn't in the download directory and can't be fetched offline, \
+                 import it first",
+                self.download_path(id)
+            ));
+        }
+        let (source, renditions) = self.renditions(id)?;
+        let part_path = self.part_path(id);
+        let no_validators = Validators::default();
+        let mut last_err = anyhow!("`{}` offers no accepted rendition", source.name());
+        for rendition in renditions {
+            let fetched = source.fetch(
+                &self.client,
+                id.as_str(),
+                rendition,
+                &no_validators,
+                &part_path,
+            );
+            let result = match fetched.await {
+                Ok(Fetched::Modified { body, validators }) => {
+                    self.store(id, rendition, body, validators).await
+                }
+                Ok(Fetched::NotModified) => {
+                    Err(anyhow!("unexpected `304 Not Modified` for {rendition}"))
+                }
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) => {
+                    info!("fetched {rendition} of emote `{id:?}`");
+                    return Ok(rendition);
+                }
+                Err(err) => {
+                    crate::fs::remove(&part_path).await?;
+                    warn!("couldn't fetch {rendition} of emote `{id:?}`: {err}");
+                    last_err = err;
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Moves a streamed download into place as WebP and remembers how to revalidate it.
+    ///
+    /// The format is taken from the content, servers don't always send what the URL says.
+    async fn store(
+        &self,
+        id: &EmoteId,
+        rendition: Rendition,
+        body: Body,
+        validators: Validators,
+    ) -> Result<()> {
+        let source = self.sources.get(id.source())?;
+        let url = source.url(id.as_str(), rendition);
+        let format = body
+            .format
+            .ok_or_else(|| anyhow!("`{url}` isn't a WebP, GIF, PNG or AVIF image"))?;
+        if format != rendition.format {
+            warn!("{rendition} of emote `{id:?}` is actually {format:?}");
+        }
+
+        let part_path = self.part_path(id);
+        let dl_path = self.download_path(id);
+        if format == source::Format::Webp {
+            tokio::fs::rename(&part_path, &dl_path).await?;
+        } else {
+            let raw_path = dl_path.with_extension(format.extension());
+            tokio::fs::rename(&part_path, &raw_path).await?;
+            let result = self.bin.magick()?.to_webp(&raw_path, &dl_path).await;
+            tokio::fs::remove_file(&raw_path).await?;
+            result?;
+        }
+
+        let entry = CacheEntry {
+            url,
+            rendition,
+            validators,
+            sha256: body.sha256,
+            fetched: chrono::Utc::now(),
+            name: cache::read(&dl_path).await.and_then(|entry| entry.name),
+        };
+        cache::write(&dl_path, &entry).await?;
+        self.recorder.rendition(id, rendition);
         Ok(())
     }
-    pub async fn download_emotes(&self, ids: &[EmoteId]) -> Result<()> {
-        let mut iter = futures::stream::iter(ids)
-            .map(|id| self.download_emote(*id))
-            .buffer_unordered(5);
-        while let Some(result) = iter.next().await {
-            if let err @ Err(_) = result {
-                return err;
+
+    /// Downloads the emote again, returns whether it changed
+    async fn refetch(&self, id: &EmoteId) -> Result<bool> {
+        let dl_path = self.download_path(id);
+        let old = tokio::fs::read(&dl_path).await.ok();
+        self.fetch_emote(id).await?;
+        let new = tokio::fs::read(&dl_path).await?;
+        Ok(old.as_deref() != Some(new.as_slice()))
+    }
+
+    /// Revalidates an existing download with a conditional request,
+    /// returns whether it changed
+    pub async fn refresh_emote(&self, id: &EmoteId) -> Result<bool> {
+        if self.offline {
+            warn!("can't revalidate emote `{id:?}` offline");
+            return Ok(false);
+        }
+        let dl_path = self.download_path(id);
+        let Some(mut entry) = cache::read(&dl_path).await else {
+            return self.refetch(id).await;
+        };
+
+        let source = self.sources.get(id.source())?;
+        let part_path = self.part_path(id);
+        let fetched = source.fetch(
+            &self.client,
+            id.as_str(),
+            entry.rendition,
+            &entry.validators,
+            &part_path,
+        );
+        let result = match fetched.await {
+            Ok(Fetched::NotModified) => Ok(false),
+            Ok(Fetched::Modified { body, validators }) if body.sha256 == entry.sha256 => {
+                crate::fs::remove(&part_path).await?;
+                entry.validators = validators;
+                entry.fetched = chrono::Utc::now();
+                cache::write(&dl_path, &entry).await.map(|()| false)
+            }
+            Ok(Fetched::Modified { body, validators }) => self
+                .store(id, entry.rendition, body, validators)
+                .await
+                .map(|()| true),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(changed) => {
+                self.recorder.rendition(id, entry.rendition);
+                Ok(changed)
+            }
+            Err(err) => {
+                crate::fs::remove(&part_path).await?;
+                warn!(
+                    "couldn't revalidate {} of emote `{id:?}`: {err}",
+                    entry.rendition
+                );
+                self.refetch(id).await
+            }
+        }
+    }
+
+    /// Registers an emote file from elsewhere as the download of `id`,
+    /// returns whether it differs from the existing download.
+    ///
+    /// `entry` is the sidecar that came with the file, its hash has to match
+    /// unless the file was converted to WebP after downloading.
+    pub async fn import_emote(
+        &self,
+        id: &EmoteId,
+        origin: &Path,
+        data: &[u8],
+        entry: Option<CacheEntry>,
+    ) -> Result<bool> {
+        let origin = origin.display().to_string();
+        let part_path = self.part_path(id);
+        let mut writer =
+            BodyWriter::create(&origin, &part_path, self.client.max_body_size()).await?;
+        let result = async {
+            writer.write(data).await?;
+            writer.finish().await
+        };
+        let body = match result.await {
+            Ok(body) => body,
+            Err(err) => {
+                crate::fs::remove(&part_path).await?;
+                return Err(err);
+            }
+        };
+        let Some(format) = body.format else {
+            crate::fs::remove(&part_path).await?;
+            return Err(anyhow!("`{origin}` isn't a WebP, GIF, PNG or AVIF image"));
+        };
+
+        let (rendition, validators, sha256) = match entry {
+            Some(entry) if entry.rendition.format == format => {
+                if entry.sha256 != body.sha256 {
+                    crate::fs::remove(&part_path).await?;
+                    return Err(anyhow!("`{origin}` doesn't match the hash of its sidecar"));
+                }
+                (entry.rendition, entry.validators, entry.sha256)
+            }
+            Some(entry) => (entry.rendition, entry.validators, entry.sha256),
+            None => {
+                let (_, renditions) = self.renditions(id)?;
+                let rendition = renditions
+                    .into_iter()
+                    .find(|rendition| rendition.format == format)
+                    .unwrap_or(Rendition::new(1, None, format));
+                (rendition, Validators::default(), body.sha256.clone())
             }
+        };
+
+        let dl_path = self.download_path(id);
+        let old = tokio::fs::read(&dl_path).await.ok();
+        let old_entry = cache::read(&dl_path).await;
+        if let Err(err) = self.store(id, rendition, body, validators).await {
+            crate::fs::remove(&part_path).await?;
+            return Err(err);
+        }
+        if let Some(mut entry) = cache::read(&dl_path).await {
+            entry.sha256 = sha256;
+            cache::write(&dl_path, &entry).await?;
+        }
+
+        if let Err(err) = self.bin.webp_info.info(&dl_path).await {
+            match &old {
+                Some(old) => tokio::fs::write(&dl_path, old).await?,
+                None => crate::fs::remove(&dl_path).await?,
+            }
+            match &old_entry {
+                Some(entry) => cache::write(&dl_path, entry).await?,
+                None => crate::fs::remove(cache::entry_path(&dl_path)).await?,
+            }
+            return Err(anyhow!("`{origin}` isn't a valid image: {err}"));
+        }
+        let new = tokio::fs::read(&dl_path).await?;
+        Ok(old.as_deref() != Some(new.as_slice()))
+    }
+
+    /// Name of the emote, looked up once and then kept in the entry of its download
+    pub async fn emote_name(&self, id: &EmoteId) -> Option<String> {
+        let dl_path = self.download_path(id);
+        let mut entry = cache::read(&dl_path).await;
+        let cached = entry.as_ref().and_then(|entry| entry.name.clone());
+        let name = match cached {
+            Some(name) => name,
+            None if self.offline => return None,
+            None => {
+                let source = self.sources.get(id.source()).ok()?;
+                let name = match source.metadata(&self.client, id.as_str()).await {
+                    Ok(meta) => meta.name,
+                    Err(err) => {
+                        warn!("couldn't look up the name of emote `{id:?}`: {err}");
+                        return None;
+                    }
+                };
+                if let Some(entry) = &mut entry {
+                    entry.name = Some(name.clone());
+                    if let Err(err) = cache::write(&dl_path, entry).await {
+                        warn!("couldn't remember the name of emote `{id:?}`: {err}");
+                    }
+                }
+                name
+            }
+        };
+        self.recorder.name(id, name.clone());
+        Some(name)
+    }
+
+    /// The extracted, resized and generated frames of the emote and their stamps
+    pub fn frame_paths(&self, id: &EmoteId) -> Vec<PathBuf> {
+        vec![
+            self.raw_frames_path(id),
+            self.resized_frames_path(id),
+            cache::stamp_path(&self.resized_frames_path(id)),
+            self.motion_frames_path(id),
+            cache::stamp_path(&self.motion_frames_path(id)),
+        ]
+    }
+
+    /// Removes everything that was made from the download of the emote
+    pub async fn invalidate(&self, id: &EmoteId) -> Result<()> {
+        let mut paths = self.frame_paths(id);
+        paths.extend([self.static_out_path(id), self.anim_out_path(id)]);
+        for path in paths {
+            crate::fs::remove(&path).await?;
         }
         Ok(())
     }
 
-    pub async fn webp_info(&self, id: EmoteId) -> Result<webp::WebpInfo> {
+    pub async fn download_emote(&self, id: &EmoteId) -> Result<()> {
+        self.fetch_emote(id).await?;
+        info!("downloaded emote `{id:?}`");
+        Ok(())
+    }
+    pub async fn download_emotes(&self, ids: &[EmoteId]) -> Vec<BatchElement<()>> {
+        batch::run(
+            ids,
+            self.settings.concurrency.process,
+            self.fail_fast,
+            |id| async move {
+                let result = self.download_emote(id).await;
+                BatchElement {
+                    id: id.clone(),
+                    result: result.map_err(|source| Error::Download {
+                        id: id.clone(),
+                        source,
+                    }),
+                }
+            },
+        )
+        .await
+    }
+
+    pub async fn webp_info(&self, id: &EmoteId) -> Result<webp::WebpInfo> {
         let info = self.bin.webp_info.info(self.download_path(id)).await?;
         info!("got webp_info for emote `{id:?}`");
         Ok(info)
     }
-    pub async fn webp_infos(&self, ids: &[EmoteId]) -> Result<Vec<webp::WebpInfo>> {
-        let mut infos = Vec::with_capacity(ids.len());
-        let mut iter = futures::stream::iter(ids)
-            .map(|id| self.webp_info(*id))
-            .buffer_unordered(5);
-        while let Some(info) = iter.next().await {
-            infos.push(info?);
-        }
-        Ok(infos)
+    pub async fn webp_infos(&self, ids: &[EmoteId]) -> Vec<BatchElement<webp::WebpInfo>> {
+        batch::run(
+            ids,
+            self.settings.concurrency.process,
+            self.fail_fast,
+            |id| async move {
+                let result = self.webp_info(id).await;
+                BatchElement {
+                    id: id.clone(),
+                    result: result.map_err(|source| Error::Probe {
+                        id: id.clone(),
+                        source,
+                    }),
+                }
+            },
+        )
+        .await
     }
 
     pub async fn downloaded_emotes(&self) -> Vec<PathBuf> {
-        let dl_dir = self.opt.download_dir.clone();
+        let dl_dir = self.settings.dirs.download.clone();
         tokio::task::spawn_blocking(move || -> Vec<PathBuf> {
             WalkDir::new(dl_dir)
                 .max_depth(1)
@@ -108,7 +467,7 @@ impl Context {
         .unwrap()
     }
 
-    pub async fn extract_frames_single(&self, id: EmoteId) -> Result<()> {
+    pub async fn extract_frames_single(&self, id: &EmoteId) -> Result<()> {
         let src = self.download_path(id);
         let dst = self.raw_frames_path(id);
         crate::fs::assert_dir(&dst).await?;
@@ -117,15 +476,23 @@ impl Context {
         Ok(())
     }
 
-    pub async fn extract_frames(&self, ids: &[EmoteId]) -> Result<()> {
-        let mut iter = futures::stream::iter(ids)
-            .map(|id| self.extract_frames_single(*id))
-            .buffer_unordered(5);
-        while let Some(result) = iter.next().await {
-            if let err @ Err(_) = result {
-                return err;
-            }
-        }
-        Ok(())
+    pub async fn extract_frames(&self, ids: &[EmoteId]) -> Vec<BatchElement<()>> {
+        batch::run(
+            ids,
+            self.settings.concurrency.process,
+            self.fail_fast,
+            |id| async move {
+                let result = self.extract_frames_single(id).await;
+                BatchElement {
+                    id: id.clone(),
+                    result: result.map_err(|source| Error::Extraction {
+                        id: id.clone(),
+                        stage: Stage::Extract,
+                        source,
+                    }),
+                }
+            },
+        )
+        .await
     }
 }
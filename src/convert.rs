@@ -68,6 +68,7 @@ pub enum Options {
 }
 
 impl Options {
+    #[allow(dead_code)]
     fn parse_u32(input: &str, min: i32, max: i32) -> ValidatorResult<i32> {
         let num = input.parse().map_err(|_| "invalid syntax")?;
         if num < min || num > max {
@@ -122,6 +123,7 @@ impl Options {
             .unwrap()
     }
 
+    #[allow(dead_code)]
     async fn select_scale() -> Self {
         tokio::task::spawn_blocking(|| {
             let input = Self::prompt_text("Scale (`w:h`)", |input| {
@@ -0,0 +1,218 @@
+use std::io::ErrorKind;
+use std::path::Path;
+
+use convertoid::binaries::{self, Ffmpeg, Magick, Tool, FFMPEG, MAGICK};
+use convertoid::config::{Http, Settings};
+use convertoid::download::Client;
+use convertoid::error::HttpError;
+use convertoid::source::Sources;
+
+/// Counts the problems while printing the checks
+#[derive(Debug, Default)]
+struct Report {
+    problems: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&mut self, what: &str, detail: impl AsRef<str>) {
+        println!("  ok    {what:<14} {}", detail.as_ref());
+    }
+    fn warn(&mut self, what: &str, detail: impl AsRef<str>, hint: impl AsRef<str>) {
+        self.warnings += 1;
+        println!("  warn  {what:<14} {}", detail.as_ref());
+        println!("        {:<14} hint: {}", "", hint.as_ref());
+    }
+    fn fail(&mut self, what: &str, detail: impl AsRef<str>, hint: impl AsRef<str>) {
+        self.problems += 1;
+        println!("  FAIL  {what:<14} {}", detail.as_ref());
+        println!("        {:<14} hint: {}", "", hint.as_ref());
+    }
+}
+
+fn missing_hint(tool: &Tool) -> String {
+    format!(
+        "install `{}` or set `{}` (also in `.env`) or `binaries.{}` in the config",
+        tool.program, tool.var, tool.key
+    )
+}
+
+async fn check_tools(report: &mut Report, settings: &Settings) {
+    println!("binaries:");
+    for (tool, located) in binaries::locate_all(&settings.binaries) {
+        let name = tool.name;
+        let path = match located {
+            Ok(path) => path,
+            Err(err) if tool.optional => {
+                report.warn(name, format!("{err}, it's optional"), missing_hint(&tool));
+                continue;
+            }
+            Err(err) => {
+                report.fail(name, err.to_string(), missing_hint(&tool));
+                continue;
+            }
+        };
+        match binaries::check_version(&tool, &path).await {
+            Ok(info) => {
+                let version = info
+                    .version
+                    .map_or("unknown version".into(), |v| v.to_string());
+                report.ok(name, format!("{version} at {path:?}"));
+            }
+            Err(err) => {
+                let hint = format!(
+                    "update `{}` or point `{}` to a newer one",
+                    tool.program, tool.var
+                );
+                report.fail(name, format!("{path:?}: {err}"), hint);
+                continue;
+            }
+        }
+
+        if tool.name == FFMPEG.name {
+            check_ffmpeg(report, &Ffmpeg::new(&path)).await;
+        } else if tool.name == MAGICK.name {
+            check_magick(report, &Magick::new(&path)).await;
+        }
+    }
+}
+
+async fn check_ffmpeg(report: &mut Report, ffmpeg: &Ffmpeg) {
+    let encoders = [
+        ("libwebp", "`--enable-libwebp`"),
+        ("libvpx-vp9", "`--enable-libvpx`"),
+    ];
+    for (encoder, flag) in encoders {
+        let what = format!("  {encoder}");
+        match ffmpeg.has_encoder(encoder).await {
+            Ok(true) => report.ok(&what, "encoder available"),
+            Ok(false) => report.fail(
+                &what,
+                "encoder missing",
+                format!("use an ffmpeg built with {flag}, e.g. a full build"),
+            ),
+            Err(err) => report.fail(&what, err.to_string(), "check that ffmpeg runs at all"),
+        }
+    }
+}
+
+async fn check_magick(report: &mut Report, magick: &Magick) {
+    match magick.can_read("SVG").await {
+        Ok(true) => report.ok("  svg", "delegate available"),
+        Ok(false) => report.warn(
+            "  svg",
+            "can't read SVG",
+            "install ImageMagick with the librsvg delegate to convert SVG emotes",
+        ),
+        Err(err) => report.warn("  svg", err.to_string(), "check that magick runs at all"),
+    }
+}
+
+/// Writes a file into the existing directory and removes it again
+async fn probe_dir(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(".convertoid-doctor");
+    tokio::fs::write(&probe, b"").await?;
+    tokio::fs::remove_file(&probe).await
+}
+
+async fn check_dirs(report: &mut Report, settings: &Settings) {
+    println!("directories:");
+    let dirs = &settings.dirs;
+    let all = [
+        ("download", &dirs.download, "--dl-dir"),
+        ("raw_frames", &dirs.raw_frames, "--raw-frames-dir"),
+        (
+            "resized_frames",
+            &dirs.resized_frames,
+            "--resized-frames-dir",
+        ),
+        ("out_static", &dirs.out_static, "--out-static-dir"),
+        ("out_anim", &dirs.out_anim, "--out-anim-dir"),
+    ];
+    for (name, dir, flag) in all {
+        let moved = format!("move it with `{flag}` or `dirs.{name}`");
+        match tokio::fs::metadata(dir).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let hint = format!("the next run creates it, or {moved}");
+                report.warn(name, format!("{dir:?} doesn't exist"), hint);
+                continue;
+            }
+            // with a trailing slash the lookup of a file already fails
+            Err(err) if err.kind() == ErrorKind::NotADirectory => {
+                report.fail(name, format!("{dir:?} isn't a directory"), moved);
+                continue;
+            }
+            Ok(metadata) if !metadata.is_dir() => {
+                report.fail(name, format!("{dir:?} isn't a directory"), moved);
+                continue;
+            }
+            _ => {}
+        }
+        match probe_dir(dir).await {
+            Ok(()) => report.ok(name, format!("{dir:?} is writable")),
+            Err(err) => report.fail(
+                name,
+                format!("{dir:?} isn't writable: {err}"),
+                format!("fix its permissions or {moved}"),
+            ),
+        }
+    }
+}
+
+async fn check_sources(report: &mut Report, settings: &Settings, sources: &Sources, offline: bool) {
+    println!("sources:");
+    // One attempt is enough to tell whether a host is reachable
+    let client = Client::new(&Http {
+        retries: 0,
+        ..settings.http.clone()
+    });
+    for name in sources.names() {
+        let Ok(source) = sources.get(name) else {
+            continue;
+        };
+        let Some(&rendition) = source.renditions().first() else {
+            continue;
+        };
+        let Ok(url) = reqwest::Url::parse(&source.url("doctor", rendition)) else {
+            continue;
+        };
+        let host = match url.host_str() {
+            Some(host) if url.scheme().starts_with("http") => host,
+            _ => {
+                report.ok(name, "local, nothing to reach");
+                continue;
+            }
+        };
+        if offline {
+            report.ok(name, format!("`{host}` not checked, offline"));
+            continue;
+        }
+
+        let root = format!("{}://{host}/", url.scheme());
+        match client.send(&root, &Default::default()).await {
+            Ok(_) | Err(HttpError::Status { .. }) => {
+                report.ok(name, format!("`{host}` is reachable"))
+            }
+            Err(err) => report.fail(
+                name,
+                err.to_string(),
+                "check the network and proxy settings, or `import` the emotes and use `--offline`",
+            ),
+        }
+    }
+}
+
+/// Prints the state of the binaries, directories and sources, returns the exit code
+pub async fn run(settings: &Settings, sources: &Sources, offline: bool) -> i32 {
+    let mut report = Report::default();
+    check_tools(&mut report, settings).await;
+    check_dirs(&mut report, settings).await;
+    check_sources(&mut report, settings, sources, offline).await;
+
+    println!("{} problems, {} warnings", report.problems, report.warnings);
+    if report.problems > 0 {
+        1
+    } else {
+        0
+    }
+}
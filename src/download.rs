@@ -1,51 +1,389 @@
-use std::path::{Path, PathBuf};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Result;
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use log::warn;
+use reqwest::header::{
+    HeaderMap, HeaderValue, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    RETRY_AFTER,
+};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
 
-use crate::emote_ext::{EmoteId, EmoteIdExt};
+use crate::config::Http;
+use crate::error::HttpError;
+use crate::source::Format;
 
 const USER_AGENT: &str = "\
     Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
     AppleWebKit/537.36 (KHTML, like Gecko) \
     Chrome/110.0.0.0 Safari/537.36";
 
+/// Enough bytes to tell the supported image formats apart
+const SNIFF_LEN: usize = 16;
+
+/// Limits the requests to a single host
+#[derive(Debug)]
+struct Host {
+    permits: Semaphore,
+    /// Earliest time the next request may start
+    next: tokio::sync::Mutex<Instant>,
+}
+
+/// `ETag` and `Last-Modified` of a previous response, to make conditional requests
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Validators {
+        let get = |name| Some(headers.get(name)?.to_str().ok()?.to_string());
+        Validators {
+            etag: get(ETAG),
+            last_modified: get(LAST_MODIFIED),
+        }
+    }
+    fn to_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let values = [
+            (IF_NONE_MATCH, &self.etag),
+            (IF_MODIFIED_SINCE, &self.last_modified),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        headers
+    }
+}
+
+/// A body that was written to a file
 #[derive(Debug, Clone)]
-pub struct Client(reqwest::Client);
+pub struct Body {
+    pub size: u64,
+    pub sha256: String,
+    /// Format recognized from the first bytes, regardless of the URL and `Content-Type`
+    pub format: Option<Format>,
+}
+
+/// Streams a body to a file while hashing it and sniffing its format
+#[derive(Debug)]
+pub struct BodyWriter {
+    url: String,
+    max_size: u64,
+    file: tokio::fs::File,
+    hasher: Sha256,
+    head: Vec<u8>,
+    size: u64,
+}
+
+impl BodyWriter {
+    /// `url` is only used in errors
+    pub async fn create(url: &str, path: &Path, max_size: u64) -> Result<BodyWriter> {
+        Ok(BodyWriter {
+            url: url.to_string(),
+            max_size,
+            file: tokio::fs::File::create(path).await?,
+            hasher: Sha256::new(),
+            head: Vec::with_capacity(SNIFF_LEN),
+            size: 0,
+        })
+    }
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.size += chunk.len() as u64;
+        if self.size > self.max_size {
+            return Err(HttpError::TooLarge {
+                url: self.url.clone(),
+                max_size: self.max_size,
+            }
+            .into());
+        }
+        let missing = SNIFF_LEN.saturating_sub(self.head.len()).min(chunk.len());
+        self.head.extend_from_slice(&chunk[..missing]);
+        self.hasher.update(chunk);
+        Ok(self.file.write_all(chunk).await?)
+    }
+    pub async fn finish(mut self) -> Result<Body> {
+        self.file.flush().await?;
+        Ok(Body {
+            size: self.size,
+            sha256: hex::encode(self.hasher.finalize()),
+            format: Format::sniff(&self.head),
+        })
+    }
+}
+
+/// Result of a possibly conditional request
+#[derive(Debug)]
+pub enum Fetched {
+    NotModified,
+    Modified { body: Body, validators: Validators },
+}
+
+/// HTTP client that retries failed requests and limits the requests per host
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: reqwest::Client,
+    http: Arc<Http>,
+    hosts: Arc<Mutex<HashMap<String, Arc<Host>>>>,
+    /// Fail every request instead of touching the network
+    offline: bool,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new(&Http::default())
+    }
+}
+
+/// Picks a random delay between half and all of `delay`
+fn jitter(delay: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    let half = delay / 2;
+    half + half.mul_f64((random % 1000) as f64 / 1000.0)
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date,
+/// requests that ask for more than `http.max_backoff_ms` aren't retried
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
 
 impl Client {
-    pub fn new() -> Client {
-        Client(
-            reqwest::ClientBuilder::new()
+    pub fn new(http: &Http) -> Client {
+        Client {
+            inner: reqwest::ClientBuilder::new()
                 .user_agent(USER_AGENT)
+                .timeout(Duration::from_secs(http.timeout_secs))
                 .build()
                 .unwrap(),
-        )
+            http: Arc::new(http.clone()),
+            hosts: Default::default(),
+            offline: false,
+        }
+    }
+    /// A client that fails every request with [`HttpError::Offline`]
+    pub fn offline(self) -> Client {
+        Client {
+            offline: true,
+            ..self
+        }
+    }
+    pub fn is_offline(&self) -> bool {
+        self.offline
     }
 
-    pub async fn get(&self, url: &str, file_name: impl AsRef<Path>) -> Result<Download> {
-        let resp = self.0.get(url).send().await?;
-        let bytes = resp.bytes().await?;
-        Ok(Download::new(file_name.as_ref().to_owned(), bytes))
+    /// Largest body that is accepted, see [`Http::max_body_size`]
+    pub fn max_body_size(&self) -> u64 {
+        self.http.max_body_size
     }
-    pub async fn get_emote(&self, emote: EmoteId) -> Result<Download> {
-        let resp = self.0.get(emote.to_url()).send().await?;
-        let bytes = resp.bytes().await?;
-        Ok(Download::new(emote.to_file_name(), bytes))
+
+    fn host(&self, url: &str) -> Arc<Host> {
+        let name = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| Some(url.host_str()?.to_string()))
+            .unwrap_or_default();
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(name)
+            .or_insert_with(|| {
+                Arc::new(Host {
+                    permits: Semaphore::new(self.http.per_host_concurrency),
+                    next: tokio::sync::Mutex::new(Instant::now()),
+                })
+            })
+            .clone()
     }
-}
 
-#[derive(Default, Debug)]
-pub struct Download {
-    pub file_name: PathBuf,
-    pub data: Bytes,
-}
-impl Download {
-    pub fn new(file_name: PathBuf, data: Bytes) -> Download {
-        Self { file_name, data }
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.http.backoff_ms.saturating_mul(1 << attempt.min(16));
+        jitter(Duration::from_millis(delay.min(self.http.max_backoff_ms)))
+    }
+
+    /// A single request, waiting for the limits of the host
+    async fn send_once(
+        &self,
+        host: &Host,
+        url: &str,
+        headers: &HeaderMap,
+    ) -> reqwest::Result<reqwest::Response> {
+        let _permit = host.permits.acquire().await.unwrap();
+        {
+            let mut next = host.next.lock().await;
+            tokio::time::sleep_until(*next).await;
+            *next = Instant::now() + Duration::from_millis(self.http.per_host_interval_ms);
+        }
+        self.inner.get(url).headers(headers.clone()).send().await
     }
-    pub async fn write_to(&self, dir: impl AsRef<Path>) -> Result<()> {
-        let path = dir.as_ref().join(&self.file_name);
-        Ok(tokio::fs::write(&path, &self.data).await?)
+
+    /// Sends a GET request, retrying on timeouts, 5xx and 429 responses.
+    ///
+    /// Succeeds with 2xx and 304 responses, the body is left for the caller to read.
+    pub async fn send(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+    ) -> Result<reqwest::Response, HttpError> {
+        if self.offline {
+            let url = url.to_string();
+            return Err(HttpError::Offline { url });
+        }
+        let host = self.host(url);
+        let mut attempt = 0;
+        loop {
+            let delay = match self.send_once(&host, url, headers).await {
+                Ok(resp)
+                    if resp.status().is_success() || resp.status() == StatusCode::NOT_MODIFIED =>
+                {
+                    return Ok(resp)
+                }
+                Ok(resp) => {
+                    let status = resp.status();
+                    let retryable =
+                        status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+                    if !retryable || attempt >= self.http.retries {
+                        let url = url.to_string();
+                        return Err(HttpError::Status { url, status });
+                    }
+                    warn!("`{url}` responded with {status}");
+                    match retry_after(resp.headers()) {
+                        Some(delay) if delay > Duration::from_millis(self.http.max_backoff_ms) => {
+                            warn!("`{url}` asks to retry in {delay:?}, giving up");
+                            let url = url.to_string();
+                            return Err(HttpError::Status { url, status });
+                        }
+                        Some(delay) => delay,
+                        None => self.backoff(attempt),
+                    }
+                }
+                Err(source) => {
+                    let retryable = source.is_timeout() || source.is_connect();
+                    if !retryable || attempt >= self.http.retries {
+                        let url = url.to_string();
+                        return Err(HttpError::Request { url, source });
+                    }
+                    warn!("request to `{url}` failed: {source}");
+                    self.backoff(attempt)
+                }
+            };
+
+            warn!("retrying `{url}` in {delay:?}");
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn next_chunk(url: &str, resp: &mut reqwest::Response) -> Result<Option<Bytes>> {
+        resp.chunk().await.map_err(|source| {
+            let url = url.to_string();
+            HttpError::Request { url, source }.into()
+        })
+    }
+
+    async fn read_body(&self, url: &str, mut resp: reqwest::Response) -> Result<Bytes> {
+        let mut data = Vec::new();
+        while let Some(chunk) = Self::next_chunk(url, &mut resp).await? {
+            if (data.len() + chunk.len()) as u64 > self.http.max_body_size {
+                let url = url.to_string();
+                let max_size = self.http.max_body_size;
+                return Err(HttpError::TooLarge { url, max_size }.into());
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data.into())
+    }
+
+    async fn write_body(
+        &self,
+        url: &str,
+        mut resp: reqwest::Response,
+        path: &Path,
+    ) -> Result<Body> {
+        if resp
+            .content_length()
+            .is_some_and(|len| len > self.http.max_body_size)
+        {
+            let url = url.to_string();
+            let max_size = self.http.max_body_size;
+            return Err(HttpError::TooLarge { url, max_size }.into());
+        }
+        let mut writer = BodyWriter::create(url, path, self.http.max_body_size).await?;
+        while let Some(chunk) = Self::next_chunk(url, &mut resp).await? {
+            writer.write(&chunk).await?;
+        }
+        writer.finish().await
+    }
+
+    /// Streams the body to `path`, which is left behind if the download fails
+    pub async fn get(&self, url: &str, path: &Path) -> Result<Body> {
+        let resp = self.send(url, &HeaderMap::new()).await?;
+        self.write_body(url, resp, path).await
+    }
+    /// Fails if the server doesn't respond with a success status
+    pub async fn get_bytes(&self, url: &str) -> Result<Bytes> {
+        let resp = self.send(url, &HeaderMap::new()).await?;
+        self.read_body(url, resp).await
+    }
+    /// Also fails if the server responds with something else than an image
+    pub async fn get_image(&self, url: &str) -> Result<Bytes> {
+        let resp = self.send(url, &HeaderMap::new()).await?;
+        check_image(url, &resp)?;
+        self.read_body(url, resp).await
+    }
+    /// Streams the image to `path` unless it didn't change since the response
+    /// `validators` are taken from
+    pub async fn download_image(
+        &self,
+        url: &str,
+        validators: &Validators,
+        path: &Path,
+    ) -> Result<Fetched> {
+        let resp = self.send(url, &validators.to_headers()).await?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+        check_image(url, &resp)?;
+        let validators = Validators::from_headers(resp.headers());
+        let body = self.write_body(url, resp, path).await?;
+        Ok(Fetched::Modified { body, validators })
+    }
+    pub async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let bytes = self.get_bytes(url).await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+/// A missing `Content-Type` is accepted, the body is sniffed anyway
+fn check_image(url: &str, resp: &reqwest::Response) -> Result<(), HttpError> {
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("image/");
+    if !content_type.starts_with("image/") {
+        return Err(HttpError::ContentType {
+            url: url.to_string(),
+            content_type: content_type.to_string(),
+        });
     }
+    Ok(())
 }
@@ -0,0 +1,545 @@
+//! Draws an outline, a drop shadow and a background around the content of the frames
+//! and a caption over them
+
+use std::path::PathBuf;
+
+use ab_glyph::{point, Font, FontVec, Glyph, PxScale, ScaleFont};
+use anyhow::{anyhow, Result};
+
+use crate::config::{Caption, CaptionPosition, Color, Effects};
+use crate::image::{read_png, write_png, Image};
+
+/// Stands in for infinity in the distance transform without producing NaNs
+const FAR: f32 = 1e12;
+
+/// Squared distances to the nearest zero of `f` along one line, see
+/// "Distance Transforms of Sampled Functions" by Felzenszwalb and Huttenlocher
+fn distance_1d(f: &[f32], out: &mut [f32]) {
+    let n = f.len();
+    let mut v = vec![0; n];
+    let mut z = vec![0.0; n + 1];
+    let intersect = |q: usize, p: usize| {
+        let (qf, pf) = (q as f32, p as f32);
+        ((f[q] + qf * qf) - (f[p] + pf * pf)) / (2.0 * (qf - pf))
+    };
+
+    let mut k = 0;
+    z[0] = f32::NEG_INFINITY;
+    z[1] = f32::INFINITY;
+    for q in 1..n {
+        let mut s = intersect(q, v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersect(q, v[k]);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f32::INFINITY;
+    }
+    k = 0;
+    for (q, out) in out.iter_mut().enumerate() {
+        while z[k + 1] < q as f32 {
+            k += 1;
+        }
+        let d = q as f32 - v[k] as f32;
+        *out = d * d + f[v[k]];
+    }
+}
+
+/// Euclidean distance of every pixel to the nearest mostly opaque one
+fn distances(alpha: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let mut grid: Vec<f32> = alpha
+        .iter()
+        .map(|&a| if a >= 0.5 { 0.0 } else { FAR })
+        .collect();
+
+    let mut line = vec![0.0; width.max(height)];
+    let mut out = vec![0.0; width.max(height)];
+    for x in 0..width {
+        for y in 0..height {
+            line[y] = grid[y * width + x];
+        }
+        distance_1d(&line[..height], &mut out[..height]);
+        for y in 0..height {
+            grid[y * width + x] = out[y];
+        }
+    }
+    for row in grid.chunks_exact_mut(width) {
+        distance_1d(row, &mut out[..width]);
+        row.copy_from_slice(&out[..width]);
+    }
+    grid.iter().map(|d| d.sqrt()).collect()
+}
+
+/// Averages every value with its neighbours up to `radius` away, horizontally then vertically
+fn box_blur(values: &mut [f32], width: usize, height: usize, radius: usize) {
+    if radius == 0 {
+        return;
+    }
+    let size = (2 * radius + 1) as f32;
+    let blur_line = |get: &dyn Fn(usize) -> usize, len: usize, values: &mut [f32]| {
+        let line: Vec<f32> = (0..len).map(|i| values[get(i)]).collect();
+        let mut sum: f32 = line.iter().take(radius + 1).sum();
+        for i in 0..len {
+            values[get(i)] = sum / size;
+            if i + radius + 1 < len {
+                sum += line[i + radius + 1];
+            }
+            if i >= radius {
+                sum -= line[i - radius];
+            }
+        }
+    };
+    for y in 0..height {
+        blur_line(&|x| y * width + x, width, values);
+    }
+    for x in 0..width {
+        blur_line(&|y| y * width + x, height, values);
+    }
+}
+
+/// Straight RGBA with channels in `0..=1`
+type Pixel = [f32; 4];
+
+fn pixel(color: Color, alpha: f32) -> Pixel {
+    let [r, g, b, a] = color.0.map(|c| c as f32 / 255.0);
+    [r, g, b, a * alpha]
+}
+
+/// Composites `top` over `bottom`
+fn over(top: Pixel, bottom: Pixel) -> Pixel {
+    let alpha = top[3] + bottom[3] * (1.0 - top[3]);
+    if alpha <= 0.0 {
+        return [0.0; 4];
+    }
+    let mut out = [0.0, 0.0, 0.0, alpha];
+    for channel in 0..3 {
+        out[channel] =
+            (top[channel] * top[3] + bottom[channel] * bottom[3] * (1.0 - top[3])) / alpha;
+    }
+    out
+}
+
+fn to_pixels(image: &Image) -> Vec<Pixel> {
+    image
+        .rgba
+        .chunks_exact(4)
+        .map(|px| [px[0], px[1], px[2], px[3]].map(|c| c as f32 / 255.0))
+        .collect()
+}
+
+fn store_pixels(image: &mut Image, pixels: Vec<Pixel>) {
+    for (out, px) in image.rgba.chunks_exact_mut(4).zip(pixels) {
+        for (out, channel) in out.iter_mut().zip(px) {
+            *out = (channel * 255.0).round() as u8;
+        }
+    }
+}
+
+/// Puts the pixels over an outline of `width` around their mostly opaque parts
+fn outline(content: Vec<Pixel>, size: (usize, usize), width: u32, color: Color) -> Vec<Pixel> {
+    if width == 0 {
+        return content;
+    }
+    let alpha: Vec<f32> = content.iter().map(|px| px[3]).collect();
+    let reach = width as f32 + 1.0;
+    distances(&alpha, size.0, size.1)
+        .into_iter()
+        .zip(content)
+        .map(|(d, px)| over(px, pixel(color, (reach - d).clamp(0.0, 1.0))))
+        .collect()
+}
+
+fn decorate(image: &mut Image, effects: &Effects) {
+    let (width, height) = (image.width, image.height);
+    let mut shape = outline(
+        to_pixels(image),
+        (width, height),
+        effects.stroke_width,
+        effects.stroke_color,
+    );
+
+    if effects.shadow {
+        let mut silhouette: Vec<f32> = shape.iter().map(|px| px[3]).collect();
+        box_blur(&mut silhouette, width, height, effects.shadow_blur as usize);
+        let (dx, dy) = effects.shadow_offset;
+        for (index, px) in shape.iter_mut().enumerate() {
+            let x = (index % width) as i64 - dx as i64;
+            let y = (index / width) as i64 - dy as i64;
+            let inside = (0..width as i64).contains(&x) && (0..height as i64).contains(&y);
+            let alpha = if inside {
+                silhouette[y as usize * width + x as usize]
+            } else {
+                0.0
+            };
+            *px = over(*px, pixel(effects.shadow_color, alpha));
+        }
+    }
+    if let Some(background) = effects.background {
+        for px in &mut shape {
+            *px = over(*px, pixel(background, 1.0));
+        }
+    }
+    store_pixels(image, shape);
+}
+
+/// Places the glyphs of `text` on one line starting at the origin, returns them and the width
+fn layout(font: &FontVec, scale: PxScale, text: &str) -> (Vec<Glyph>, f32) {
+    let scaled = font.as_scaled(scale);
+    let mut glyphs = Vec::with_capacity(text.len());
+    let mut caret = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        glyphs.push(id.with_scale_and_position(scale, point(caret, scaled.ascent())));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    (glyphs, caret)
+}
+
+/// Renders the caption onto a transparent `size`x`size` layer, the same for every frame
+pub fn render_caption(
+    caption: &Caption,
+    font: Vec<u8>,
+    text: &str,
+    size: usize,
+    margin: u32,
+) -> Result<Image> {
+    let font = FontVec::try_from_vec(font).map_err(|err| anyhow!("invalid font: {err}"))?;
+    let inset = (margin + caption.stroke_width) as f32;
+    let max_width = size as f32 - 2.0 * inset;
+
+    let mut scale = PxScale::from(caption.size);
+    let (mut glyphs, mut width) = layout(&font, scale, text);
+    if caption.fit && width > max_width {
+        scale = PxScale::from(caption.size * max_width / width);
+        (glyphs, width) = layout(&font, scale, text);
+    }
+    let scaled = font.as_scaled(scale);
+    let height = scaled.ascent() - scaled.descent();
+    let left = (size as f32 - width) / 2.0;
+    let top = match caption.position {
+        CaptionPosition::Top => inset,
+        CaptionPosition::Bottom => size as f32 - inset - height,
+    };
+
+    let mut coverage = vec![0.0f32; size * size];
+    for glyph in glyphs {
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|x, y, c| {
+            let x = (left + bounds.min.x) as i64 + x as i64;
+            let y = (top + bounds.min.y) as i64 + y as i64;
+            if (0..size as i64).contains(&x) && (0..size as i64).contains(&y) {
+                let cell = &mut coverage[y as usize * size + x as usize];
+                *cell = cell.max(c);
+            }
+        });
+    }
+
+    let text: Vec<Pixel> = coverage
+        .into_iter()
+        .map(|c| pixel(caption.color, c))
+        .collect();
+    let layer = outline(
+        text,
+        (size, size),
+        caption.stroke_width,
+        caption.stroke_color,
+    );
+    let mut image = Image {
+        width: size,
+        height: size,
+        rgba: vec![0; size * size * 4],
+    };
+    store_pixels(&mut image, layer);
+    Ok(image)
+}
+
+/// Draws `layer` over the image, both have the same size
+fn overlay(image: &mut Image, layer: &Image) {
+    let pixels = to_pixels(image)
+        .into_iter()
+        .zip(to_pixels(layer))
+        .map(|(px, top)| over(top, px))
+        .collect();
+    store_pixels(image, pixels);
+}
+
+fn apply_blocking(frames: &[PathBuf], effects: &Effects, caption: Option<&Image>) -> Result<()> {
+    for path in frames {
+        let mut image = read_png(path)?;
+        if effects.is_enabled() {
+            decorate(&mut image, effects);
+        }
+        if let Some(caption) = caption {
+            if (caption.width, caption.height) != (image.width, image.height) {
+                return Err(anyhow!("the caption doesn't have the size of `{path:?}`"));
+            }
+            overlay(&mut image, caption);
+        }
+        write_png(&image, path)?;
+    }
+    Ok(())
+}
+
+/// Decorates the PNG frames in place and draws the caption layer over them
+pub async fn apply(frames: Vec<PathBuf>, effects: &Effects, caption: Option<Image>) -> Result<()> {
+    let effects = effects.clone();
+    tokio::task::spawn_blocking(move || apply_blocking(&frames, &effects, caption.as_ref())).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+
+    /// A `size`x`size` transparent image with the given pixels set
+    fn canvas(size: usize, pixels: &[((usize, usize), [u8; 4])]) -> Image {
+        let mut rgba = vec![0; size * size * 4];
+        for &((x, y), px) in pixels {
+            rgba[(y * size + x) * 4..][..4].copy_from_slice(&px);
+        }
+        Image {
+            width: size,
+            height: size,
+            rgba,
+        }
+    }
+
+    fn at(image: &Image, x: usize, y: usize) -> [u8; 4] {
+        let px = &image.rgba[(y * image.width + x) * 4..][..4];
+        [px[0], px[1], px[2], px[3]]
+    }
+
+    fn no_effects() -> Effects {
+        Effects {
+            shadow_offset: (0, 0),
+            shadow_blur: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn distances_to_a_dot() {
+        let mut alpha = vec![0.0; 7 * 7];
+        alpha[3 * 7 + 3] = 1.0;
+        let distances = distances(&alpha, 7, 7);
+        assert_eq!(distances[3 * 7 + 3], 0.0);
+        assert_eq!(distances[3 * 7 + 5], 2.0);
+        assert_eq!(distances[3], 3.0);
+        assert!((distances[4 * 7 + 4] - 2f32.sqrt()).abs() < 1e-6);
+        assert!((distances[0] - 18f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stroke_covers_its_width() {
+        let mut image = canvas(9, &[((4, 4), RED)]);
+        let effects = Effects {
+            stroke_width: 2,
+            stroke_color: Color([0, 0, 255, 255]),
+            ..no_effects()
+        };
+        decorate(&mut image, &effects);
+        assert_eq!(at(&image, 4, 4), RED);
+        for (x, y) in [(6, 4), (4, 2), (5, 5)] {
+            assert_eq!(at(&image, x, y), [0, 0, 255, 255]);
+        }
+        // antialiased between 2 and 3 pixels away, nothing beyond
+        assert!((1..255).contains(&at(&image, 6, 5)[3]));
+        assert!((1..255).contains(&at(&image, 6, 6)[3]));
+        for (x, y) in [(7, 4), (4, 1), (7, 5), (0, 0)] {
+            assert_eq!(at(&image, x, y)[3], 0);
+        }
+    }
+
+    #[test]
+    fn blur_spreads_and_keeps_the_sum() {
+        let mut values = vec![0.0; 5 * 5];
+        values[2 * 5 + 2] = 1.0;
+        box_blur(&mut values, 5, 5, 1);
+        for y in 0..5 {
+            for x in 0..5 {
+                let expected = if (1..=3).contains(&x) && (1..=3).contains(&y) {
+                    1.0 / 9.0
+                } else {
+                    0.0
+                };
+                assert!((values[y * 5 + x] - expected).abs() < 1e-6, "{x},{y}");
+            }
+        }
+    }
+
+    #[test]
+    fn shadow_is_offset_and_blurred() {
+        let effects = Effects {
+            shadow: true,
+            shadow_color: Color([0, 0, 0, 255]),
+            shadow_offset: (3, 2),
+            ..no_effects()
+        };
+        let mut image = canvas(9, &[((2, 2), RED)]);
+        decorate(&mut image, &effects);
+        assert_eq!(at(&image, 2, 2), RED);
+        assert_eq!(at(&image, 5, 4), [0, 0, 0, 255]);
+        assert_eq!(at(&image, 4, 4)[3], 0);
+
+        let mut image = canvas(9, &[((2, 2), RED)]);
+        decorate(
+            &mut image,
+            &Effects {
+                shadow_blur: 1,
+                ..effects
+            },
+        );
+        assert_eq!(at(&image, 5, 4)[3], 28);
+        assert_eq!(at(&image, 6, 5)[3], 28);
+        assert_eq!(at(&image, 7, 4)[3], 0);
+    }
+
+    #[test]
+    fn background_only_fills_transparent_pixels() {
+        let effects = Effects {
+            background: Some(Color([0, 0, 255, 255])),
+            ..no_effects()
+        };
+        let mut image = canvas(3, &[((1, 1), RED), ((0, 0), [0, 255, 0, 0])]);
+        decorate(&mut image, &effects);
+        assert_eq!(at(&image, 1, 1), RED);
+        assert_eq!(at(&image, 0, 0), [0, 0, 255, 255]);
+        assert_eq!(at(&image, 2, 2), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn nothing_is_drawn_outside_the_canvas() {
+        // neither the stroke nor the shadow wrap around to the other side
+        let effects = Effects {
+            stroke_width: 1,
+            shadow: true,
+            shadow_offset: (2, 2),
+            ..no_effects()
+        };
+        let mut image = canvas(6, &[((5, 5), RED)]);
+        decorate(&mut image, &effects);
+        assert_eq!(at(&image, 5, 5), RED);
+        assert_eq!(at(&image, 4, 5)[3], 255);
+        for (x, y) in [(0, 0), (0, 5), (5, 0), (0, 1), (1, 0)] {
+            assert_eq!(at(&image, x, y)[3], 0, "{x},{y}");
+        }
+    }
+
+    /// DejaVu Sans Mono, see `tests/fixtures/DejaVuSansMono.LICENSE`
+    const FONT: &[u8] = include_bytes!("../tests/fixtures/DejaVuSansMono.ttf");
+
+    fn caption(position: CaptionPosition, stroke_width: u32) -> Caption {
+        Caption {
+            enabled: true,
+            size: 24.0,
+            stroke_width,
+            position,
+            ..Default::default()
+        }
+    }
+
+    /// Leftmost, topmost, rightmost and bottommost pixel with any ink
+    fn ink_bounds(image: &Image) -> Option<(usize, usize, usize, usize)> {
+        let mut bounds: Option<(usize, usize, usize, usize)> = None;
+        for (index, px) in image.rgba.chunks_exact(4).enumerate() {
+            if px[3] == 0 {
+                continue;
+            }
+            let (x, y) = (index % image.width, index / image.width);
+            bounds = Some(match bounds {
+                None => (x, y, x, y),
+                Some((left, top, right, bottom)) => {
+                    (left.min(x), top.min(y), right.max(x), bottom.max(y))
+                }
+            });
+        }
+        bounds
+    }
+
+    #[test]
+    fn caption_fits_the_width() {
+        let (size, margin) = (128, 8);
+        let text = "a caption far too long for the sticker";
+        let image = render_caption(
+            &caption(CaptionPosition::Bottom, 4),
+            FONT.to_vec(),
+            text,
+            size,
+            margin,
+        )
+        .unwrap();
+        let (left, _, right, _) = ink_bounds(&image).unwrap();
+        assert!(left >= margin as usize, "starts at {left}");
+        assert!(right < size - margin as usize, "ends at {right}");
+
+        // without fitting it runs off both sides
+        let overflowing = Caption {
+            fit: false,
+            ..caption(CaptionPosition::Bottom, 4)
+        };
+        let image = render_caption(&overflowing, FONT.to_vec(), text, size, margin).unwrap();
+        let (left, _, right, _) = ink_bounds(&image).unwrap();
+        assert_eq!((left, right), (0, size - 1));
+    }
+
+    #[test]
+    fn caption_positions() {
+        let size = 128;
+        let top = render_caption(
+            &caption(CaptionPosition::Top, 2),
+            FONT.to_vec(),
+            "top",
+            size,
+            4,
+        )
+        .unwrap();
+        let (_, first, _, last) = ink_bounds(&top).unwrap();
+        assert!(first >= 4 && last < size / 2, "rows {first}..={last}");
+
+        let bottom = caption(CaptionPosition::Bottom, 2);
+        let bottom = render_caption(&bottom, FONT.to_vec(), "bottom", size, 4).unwrap();
+        let (_, first, _, last) = ink_bounds(&bottom).unwrap();
+        assert!(
+            first >= size / 2 && last < size - 4,
+            "rows {first}..={last}"
+        );
+    }
+
+    #[test]
+    fn caption_stroke_surrounds_the_letters() {
+        let render = |stroke_width| {
+            let caption = caption(CaptionPosition::Top, stroke_width);
+            render_caption(&caption, FONT.to_vec(), "I", 64, 0).unwrap()
+        };
+        let plain = render(0);
+        let stroked = render(3);
+        let (left, top, right, bottom) = ink_bounds(&plain).unwrap();
+        let (stroked_left, stroked_top, stroked_right, stroked_bottom) =
+            ink_bounds(&stroked).unwrap();
+        // the stroke pushes the text down by its width and grows it on every side,
+        // the faint antialiased edges of the letters already count as ink
+        assert_eq!(stroked_top, top);
+        assert!(stroked_bottom >= bottom + 3);
+        assert!(stroked_left + 2 <= left && stroked_right >= right + 2);
+
+        let colors: Vec<[u8; 4]> = stroked
+            .rgba
+            .chunks_exact(4)
+            .map(|px| [px[0], px[1], px[2], px[3]])
+            .collect();
+        assert!(colors.contains(&[255, 255, 255, 255]));
+        assert!(colors.contains(&[0, 0, 0, 255]));
+        assert!(!plain.rgba.chunks_exact(4).any(|px| px == [0, 0, 0, 255]));
+    }
+}
@@ -1,12 +1,21 @@
-use anyhow::Result;
-use futures::StreamExt;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{anyhow, Context as _, Result};
 use log::{info, warn};
-use simple_error::simple_error;
+use serde::{Deserialize, Serialize};
 
-use crate::binaries::Img2WebpFrame;
+use crate::animation::Timeline;
+use crate::batch::{self, BatchElement, Outcome};
+use crate::binaries::{Img2WebpFrame, Resize};
+use crate::cache;
+use crate::config::{AlphaCleanup, Caption, Effects, Motion, Scaler};
 use crate::context::Context;
 use crate::emote_ext::EmoteId;
+use crate::error::{Error, Stage, ValidationError};
 use crate::file_sequence::FileSequence;
+use crate::progress::Spinner;
 use crate::webp::WebpInfo;
 
 #[derive(Debug)]
@@ -15,104 +24,428 @@ pub struct Emote {
     pub info: WebpInfo,
     pub raw_frames: FileSequence,
     pub resized_frames: FileSequence,
+    /// Order and durations of the frames after the animation transforms, empty if static
+    pub timeline: Timeline,
+}
+
+// Defaults of the `limits` config, see
+// https://github.com/WhatsApp/stickers/blob/main/Android/app/src/main/java/com/example/samplestickerapp/StickerPackValidator.java#L30-L46
+pub const STATIC_SIZE_LIMIT: u64 = 100 * 1024;
+pub const ANIMATED_SIZE_LIMIT: u64 = 500 * 1024;
+pub const ANIMATED_MIN_FRAME_DURATION_MS: i32 = 8;
+pub const ANIMATED_MAX_TOTAL_DURATION_MS: i32 = 10_000;
+pub const STICKER_SIZE: (i32, i32) = (512, 512);
+/// Frames looked at when counting the colors of an emote
+const PIXEL_ART_SAMPLE_FRAMES: usize = 8;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Preset {
+    pub quality: i32,
+    pub level: i32,
+}
+
+impl Preset {
+    pub const fn new(quality: i32, level: i32) -> Preset {
+        Preset { quality, level }
+    }
+}
+
+impl Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "q{}/m{}", self.quality, self.level)
+    }
 }
 
-pub struct BatchElement {
+/// Default of `encoder.presets`, tried in order until the animated sticker fits
+pub const QUALITY_PRESETS: [Preset; 5] = [
+    Preset::new(75, 4),
+    Preset::new(50, 4),
+    Preset::new(25, 4),
+    Preset::new(10, 4),
+    Preset::new(10, 6),
+];
+
+#[derive(Debug)]
+pub struct Sticker {
     pub id: EmoteId,
-    pub result: Result<Emote>,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Set if the sticker only fit after falling back to a worse preset
+    pub degraded: Option<String>,
+}
+
+impl Sticker {
+    pub fn into_outcome(self) -> Outcome {
+        match self.degraded {
+            None => Outcome::Succeeded {
+                path: self.path,
+                size: Some(self.size),
+            },
+            Some(reason) => Outcome::Degraded {
+                path: self.path,
+                size: self.size,
+                reason,
+            },
+        }
+    }
+}
+
+/// How the cached frames of a directory were made, they're made again when it changes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FramesStamp {
+    /// The motion preset and the number of frames, only for generated frames
+    motion: Option<(Motion, usize)>,
+    /// The [`Decoration`] drawn onto the frames, `null` if they're bare
+    decoration: serde_json::Value,
+}
+
+/// The effects, caption and edge cleanup applied to the frames, `None` when disabled
+#[derive(Serialize)]
+struct Decoration<'a> {
+    effects: Option<&'a Effects>,
+    caption: Option<(&'a Caption, String)>,
+    alpha: Option<&'a AlphaCleanup>,
 }
 
 impl Emote {
-    pub async fn download(ctx: &Context, id: EmoteId) -> Result<()> {
+    pub async fn download(ctx: &Context, id: &EmoteId) -> Result<()> {
+        let start = Instant::now();
         let dl_path = ctx.download_path(id);
 
-        if tokio::fs::metadata(&dl_path).await.is_ok() {
+        let executed = if tokio::fs::metadata(&dl_path).await.is_err() {
+            ctx.fetch_emote(id).await?;
+            info!("downloaded emote `{id:?}`");
+            true
+        } else if !ctx.refresh {
             warn!("emote `{id:?}` was already downloaded");
-            return Ok(());
-        }
-
-        let dl = ctx.client.get_emote(id).await?;
-        dl.write_to(dl_path).await?;
-
-        info!("downloaded emote `{id:?}`");
+            false
+        } else {
+            let changed = ctx.refresh_emote(id).await?;
+            if changed {
+                info!("emote `{id:?}` changed, invalidating its frames and stickers");
+                ctx.invalidate(id).await?;
+            } else {
+                info!("emote `{id:?}` didn't change");
+            }
+            changed
+        };
+        ctx.emote_name(id).await;
+        ctx.finish_stage(id, Stage::Download, executed, start.elapsed());
         Ok(())
     }
 
-    pub async fn webp_info(ctx: &Context, id: EmoteId) -> Result<WebpInfo> {
+    pub async fn webp_info(ctx: &Context, id: &EmoteId) -> Result<WebpInfo> {
+        let start = Instant::now();
         let info = ctx.bin.webp_info.info(ctx.download_path(id)).await?;
         info!("got webp_info for emote `{id:?}`");
+        ctx.finish_stage(id, Stage::Probe, true, start.elapsed());
+        ctx.recorder.info(id, &info);
         Ok(info)
     }
 
-    pub async fn extract_frames(ctx: &Context, id: EmoteId) -> Result<FileSequence> {
+    pub async fn extract_frames(ctx: &Context, id: &EmoteId) -> Result<FileSequence> {
+        let start = Instant::now();
         let dst = ctx.raw_frames_path(id);
         crate::fs::assert_dir(&dst).await?;
 
-        if !crate::fs::is_dir_empty(&dst).await? {
+        let executed = crate::fs::is_dir_empty(&dst).await?;
+        if !executed {
             warn!("frames for emote `{id:?}` are already extracted");
         } else {
             let src = ctx.download_path(id);
             ctx.bin.anim_dump.dump_frames(&src, &dst).await?;
             info!("extracted frames for emote `{id:?}`");
         }
+        ctx.finish_stage(id, Stage::Extract, executed, start.elapsed());
 
         crate::file_sequence::file_sequence(&dst).await
     }
 
-    pub async fn resize_frames(ctx: &Context, id: EmoteId) -> Result<FileSequence> {
+    /// Nearest-neighbour for small emotes with few colors, lanczos for everything else
+    async fn pick_scaler(
+        ctx: &Context,
+        id: &EmoteId,
+        info: &WebpInfo,
+        frames: &Path,
+    ) -> Result<Scaler> {
+        let scaling = &ctx.settings.scaling;
+        let (width, height) = info.size;
+        if width.max(height) > scaling.pixel_art_max_size {
+            return Ok(Scaler::Lanczos);
+        }
+        let colors = ctx
+            .bin
+            .ffmpeg
+            .count_colors(frames, PIXEL_ART_SAMPLE_FRAMES)
+            .await?;
+        if colors > scaling.pixel_art_max_colors {
+            return Ok(Scaler::Lanczos);
+        }
+        info!("emote `{id:?}` looks like pixel art ({width}x{height}, {colors} colors)");
+        Ok(Scaler::Nearest)
+    }
+
+    /// The configured caption or the name of the emote, its ID if the name isn't known
+    async fn caption_text(ctx: &Context, id: &EmoteId) -> String {
+        if let Some(text) = &ctx.settings.caption.text {
+            return text.clone();
+        }
+        match ctx.emote_name(id).await {
+            Some(name) => name,
+            None => id.as_str().to_string(),
+        }
+    }
+
+    async fn render_caption(ctx: &Context, text: String) -> Result<crate::image::Image> {
+        let caption = &ctx.settings.caption;
+        let font_path = caption
+            .font
+            .as_ref()
+            .ok_or_else(|| anyhow!("`caption.font` isn't set"))?;
+        let font = tokio::fs::read(font_path)
+            .await
+            .with_context(|| format!("couldn't read the caption font `{font_path:?}`"))?;
+        let size = ctx.settings.output.size as usize;
+        let margin = ctx.settings.framing.margin;
+        let layer = tokio::task::spawn_blocking({
+            let caption = caption.clone();
+            move || crate::effects::render_caption(&caption, font, &text, size, margin)
+        })
+        .await??;
+        Ok(layer)
+    }
+
+    /// The motion preset of the emote, only static emotes are animated
+    pub fn motion(ctx: &Context, id: &EmoteId, info: &WebpInfo) -> Option<Motion> {
+        let animation = ctx.settings.animation.for_emote(&format!("{id:?}"));
+        animation.motion.filter(|_| !info.is_animated())
+    }
+
+    /// The decoration the settings ask for, as stored in the [`FramesStamp`]
+    fn decoration(ctx: &Context, caption: Option<&str>) -> Result<serde_json::Value> {
+        let settings = &ctx.settings;
+        let caption = caption.map(|text| (&settings.caption, text.to_string()));
+        let alpha = &settings.alpha;
+        let decoration = Decoration {
+            effects: Some(&settings.effects).filter(|effects| effects.is_enabled()),
+            caption,
+            alpha: Some(alpha).filter(|alpha| alpha.bleed || alpha.threshold.is_some()),
+        };
+        if decoration.effects.is_none()
+            && decoration.caption.is_none()
+            && decoration.alpha.is_none()
+        {
+            return Ok(serde_json::Value::Null);
+        }
+        Ok(serde_json::to_value(decoration)?)
+    }
+
+    /// Removes the cached frames in `dir` if they weren't made like `stamp` says
+    async fn discard_stale(id: &EmoteId, dir: &Path, stamp: &FramesStamp) -> Result<()> {
+        if !crate::fs::is_dir_empty(dir).await?
+            && cache::read_stamp::<FramesStamp>(dir).await.as_ref() != Some(stamp)
+        {
+            warn!("the settings of emote `{id:?}` changed, discarding its frames in {dir:?}");
+            crate::fs::remove(dir).await?;
+            crate::fs::assert_dir(dir).await?;
+        }
+        Ok(())
+    }
+
+    /// Generates the frames of a static emote moving like `motion`,
+    /// returns them and their stamp if they were generated now
+    async fn animate(
+        ctx: &Context,
+        id: &EmoteId,
+        resized_frames: &FileSequence,
+        motion: Motion,
+        decoration: serde_json::Value,
+    ) -> Result<(FileSequence, Option<FramesStamp>)> {
+        let dst = ctx.motion_frames_path(id);
+        let frames = ctx
+            .settings
+            .animation
+            .for_emote(&format!("{id:?}"))
+            .motion_frames;
+        let stamp = FramesStamp {
+            motion: Some((motion, frames)),
+            decoration,
+        };
+        crate::fs::assert_dir(&dst).await?;
+        Self::discard_stale(id, &dst, &stamp).await?;
+
+        let executed = crate::fs::is_dir_empty(&dst).await?;
+        if !executed {
+            warn!("frames for emote `{id:?}` are already animated");
+        } else {
+            let src = resized_frames.dir.join(&resized_frames.files[0].file_name);
+            crate::motion::generate(src, dst.clone(), motion, frames).await?;
+            info!("animated emote `{id:?}` with {motion:?}");
+        }
+        let frames = crate::file_sequence::file_sequence(&dst).await?;
+        Ok((frames, executed.then_some(stamp)))
+    }
+
+    /// Draws the effects and the caption onto the frames and cleans up their edges
+    async fn decorate(
+        ctx: &Context,
+        id: &EmoteId,
+        frames: &FileSequence,
+        caption: Option<String>,
+    ) -> Result<()> {
+        let paths = || {
+            let paths = frames.files.iter();
+            paths.map(|file| frames.dir.join(&file.file_name)).collect()
+        };
+
+        let effects = &ctx.settings.effects;
+        if effects.is_enabled() || caption.is_some() {
+            let caption = match caption {
+                Some(text) => Some(Self::render_caption(ctx, text).await?),
+                None => None,
+            };
+            crate::effects::apply(paths(), effects, caption).await?;
+            info!("decorated the frames of emote `{id:?}`");
+        }
+        let alpha = &ctx.settings.alpha;
+        if alpha.bleed || alpha.threshold.is_some() {
+            let record = crate::alpha::clean_frames(paths(), alpha).await?;
+            info!(
+                "cleaned up the edges of emote `{id:?}`, chroma error {:.2} -> {:.2}",
+                record.chroma_error_before, record.chroma_error_after
+            );
+            ctx.recorder.alpha(id, record);
+        }
+        Ok(())
+    }
+
+    /// Fits the frames into the sticker canvas and animates them with the configured motion,
+    /// then decorates them and cleans up their edges. `info` describes the download
+    pub async fn resize_frames(
+        ctx: &Context,
+        id: &EmoteId,
+        info: &WebpInfo,
+    ) -> Result<FileSequence> {
+        let start = Instant::now();
         let dst = ctx.resized_frames_path(id);
+        let motion = Self::motion(ctx, id, info);
+        let caption = match ctx.settings.caption.enabled {
+            true => Some(Self::caption_text(ctx, id).await),
+            false => None,
+        };
+        let decoration = Self::decoration(ctx, caption.as_deref())?;
+        // the motion moves the bare emote, the effects and the caption stay in place
+        let stamp = FramesStamp {
+            motion: None,
+            decoration: match motion {
+                Some(_) => serde_json::Value::Null,
+                None => decoration.clone(),
+            },
+        };
         crate::fs::assert_dir(&dst).await?;
+        Self::discard_stale(id, &dst, &stamp).await?;
 
-        if !crate::fs::is_dir_empty(&dst).await? {
+        let executed = crate::fs::is_dir_empty(&dst).await?;
+        if !executed {
             warn!("frames for emote `{id:?}` are already resized");
         } else {
             let src = ctx.raw_frames_path(id).join("%04d.png");
-            let dst = dst.join("%04d.png");
-            ctx.bin.ffmpeg.resize_images(src, dst).await?;
+            let dst_pattern = dst.join("%04d.png");
+            let framing = &ctx.settings.framing;
+            let crop = if framing.trim {
+                let bbox = ctx
+                    .bin
+                    .ffmpeg
+                    .content_bbox(&src, framing.alpha_threshold)
+                    .await?;
+                match bbox {
+                    Some(bbox) => info!("trimming emote `{id:?}` to {bbox:?}"),
+                    None => warn!("emote `{id:?}` is fully transparent, not trimming it"),
+                }
+                bbox
+            } else {
+                None
+            };
+            let scaler = match ctx.settings.scaling.scaler {
+                Scaler::Auto => Self::pick_scaler(ctx, id, info, &src).await?,
+                scaler => scaler,
+            };
+            let resize = Resize {
+                size: ctx.settings.output.size,
+                crop,
+                margin: framing.margin + ctx.settings.effects.extent(),
+                align: framing.align,
+                scaler,
+                premultiply: ctx.settings.alpha.premultiply,
+            };
+            ctx.bin
+                .ffmpeg
+                .resize_images(src, &dst_pattern, &resize)
+                .await?;
             info!("resized frames for emote `{id:?}`");
         }
+        let mut frames = crate::file_sequence::file_sequence(&dst).await?;
+        // the stamp of frames made now, written once they're decorated
+        let mut pending = executed.then_some(stamp);
+        if let Some(motion) = motion {
+            if let Some(stamp) = pending.take() {
+                cache::write_stamp(&dst, &stamp).await?;
+            }
+            (frames, pending) = Self::animate(ctx, id, &frames, motion, decoration).await?;
+        }
+        if let Some(stamp) = pending {
+            Self::decorate(ctx, id, &frames, caption).await?;
+            cache::write_stamp(&frames.dir, &stamp).await?;
+        }
+        ctx.finish_stage(id, Stage::Resize, executed, start.elapsed());
 
-        crate::file_sequence::file_sequence(&dst).await
+        Ok(frames)
     }
 
-    async fn to_sticker_static(&self, ctx: &Context) -> Result<()> {
+    async fn to_sticker_static(&self, ctx: &Context, spinner: &Spinner) -> Result<Sticker, Error> {
+        let start = Instant::now();
         let src_file_name = &self.resized_frames.files[0].file_name;
         let src = self.resized_frames.dir.join(src_file_name);
-        let dst = ctx.static_out_path(self.id);
-        ctx.bin.magick.convert(src, dst, false).await?;
-        info!("converted emote `{:?}` to static sticker", self.id);
-        Ok(())
-    }
-    async fn to_sticker_anim(&self, ctx: &Context) -> Result<()> {
-        #[derive(Debug, Clone, Copy)]
-        struct Preset {
-            pub quality: i32,
-            pub level: i32,
-        }
-        impl Preset {
-            pub const fn new(quality: i32, level: i32) -> Preset {
-                Preset { quality, level }
-            }
+        let dst = ctx.static_out_path(&self.id);
+        let output = &ctx.settings.output;
+        let limit = ctx.settings.limits.static_size;
+        let encode_err = |source| Error::Encode {
+            id: self.id.clone(),
+            source,
+        };
+
+        spinner.set_message("encoding");
+        ctx.bin
+            .magick()?
+            .convert(src, &dst, output.static_lossless, output.size)
+            .await
+            .map_err(encode_err)?;
+
+        let size = crate::fs::file_size(&dst).await.map_err(encode_err)?;
+        ctx.finish_stage(&self.id, Stage::Encode, true, start.elapsed());
+        self.record_size(ctx, size, limit);
+        if size > limit {
+            return Err(Error::SizeLimit {
+                id: self.id.clone(),
+                size,
+                limit,
+            });
         }
 
-        // https://github.com/WhatsApp/stickers/blob/main/Android/app/src/main/java/com/example/samplestickerapp/StickerPackValidator.java#L30-L46
-        const STATIC_SIZE_LIMIT: u64 = 100 * 1024;
-        const ANIMATED_SIZE_LIMIT: u64 = 500 * 1024;
-
-        const QUALITY_PRESETS: [Preset; 5] = [
-            Preset::new(75, 4),
-            Preset::new(50, 4),
-            Preset::new(25, 4),
-            Preset::new(10, 4),
-            Preset::new(10, 6),
-        ];
-
-        fn make_frames(resized_frames: &FileSequence, durations: &[i32]) -> Vec<Img2WebpFrame> {
+        info!("converted emote `{:?}` to static sticker", self.id);
+        Ok(Sticker {
+            id: self.id.clone(),
+            path: dst,
+            size,
+            degraded: None,
+        })
+    }
+    async fn to_sticker_anim(&self, ctx: &Context, spinner: &Spinner) -> Result<Sticker, Error> {
+        fn make_frames(resized_frames: &FileSequence, timeline: &Timeline) -> Vec<Img2WebpFrame> {
             let base_dir = resized_frames.dir.as_path();
-            let mut frames = Vec::with_capacity(resized_frames.files.len());
-            let resized = resized_frames.files.iter();
-            for (frame, &duration) in resized.zip(durations.iter()) {
-                let path = base_dir.join(&frame.file_name);
+            let mut frames = Vec::with_capacity(timeline.frames.len());
+            for (&index, &duration) in timeline.frames.iter().zip(&timeline.durations) {
+                let path = base_dir.join(&resized_frames.files[index].file_name);
                 frames.push(Img2WebpFrame::new(path, duration, 0, 0))
             }
             frames
@@ -124,102 +457,239 @@ impl Emote {
             });
         }
 
-        let output = ctx.anim_out_path(self.id);
-        let mut frames = make_frames(&self.resized_frames, &self.info.durations);
-
-        let mut success = false;
-        for preset in QUALITY_PRESETS {
+        let start = Instant::now();
+        let output = ctx.anim_out_path(&self.id);
+        let limit = ctx.settings.limits.animated_size;
+        let mut frames = make_frames(&self.resized_frames, &self.timeline);
+        let loop_count = ctx
+            .settings
+            .animation
+            .for_emote(&format!("{:?}", self.id))
+            .loop_count;
+        let encode_err = |source| Error::Encode {
+            id: self.id.clone(),
+            source,
+        };
+
+        let mut size = 0;
+        for (index, &preset) in ctx.settings.encoder.presets.iter().enumerate() {
             alter_frames(&mut frames, preset);
+            spinner.set_message(format!("encoding with {preset:?}"));
 
             ctx.bin
                 .img_2_webp
-                .webp_from_images(&output, &frames)
-                .await?;
+                .webp_from_images(&output, &frames, loop_count)
+                .await
+                .map_err(encode_err)?;
 
-            if crate::fs::file_size(&output).await? > ANIMATED_SIZE_LIMIT {
+            size = crate::fs::file_size(&output).await.map_err(encode_err)?;
+            if size > limit {
                 warn!("emote `{:?}` too large with {:?}", self.id, preset);
             } else {
+                ctx.finish_stage(&self.id, Stage::Encode, true, start.elapsed());
+                ctx.recorder.preset(&self.id, preset);
+                self.record_size(ctx, size, limit);
                 info!(
                     "converted emote `{:?}` to animated sticker with {:?}",
                     self.id, preset
                 );
-                success = true;
-                break;
+                let degraded = (index > 0).then(|| format!("needed {preset:?}"));
+                return Ok(Sticker {
+                    id: self.id.clone(),
+                    path: output,
+                    size,
+                    degraded,
+                });
             }
         }
 
-        if !success {
-            warn!("emote `{:?}` too large with every preset", self.id);
-        }
-
-        Ok(())
+        ctx.finish_stage(&self.id, Stage::Encode, true, start.elapsed());
+        self.record_size(ctx, size, limit);
+        Err(Error::SizeLimit {
+            id: self.id.clone(),
+            size,
+            limit,
+        })
     }
-    pub async fn to_sticker(&self, ctx: &Context) -> Result<()> {
-        if self.info.is_animated() {
-            self.to_sticker_anim(ctx).await
+    fn record_size(&self, ctx: &Context, size: u64, limit: u64) {
+        ctx.recorder.validation(
+            &self.id,
+            "size_limit",
+            size as i64,
+            limit as i64,
+            size <= limit,
+        );
+    }
+    /// Whether the sticker is animated, static emotes are when they get a motion
+    pub fn is_animated(&self) -> bool {
+        !self.timeline.frames.is_empty()
+    }
+    pub async fn to_sticker(&self, ctx: &Context) -> Result<Sticker, Error> {
+        let spinner = ctx.progress.spinner(&self.id);
+        if self.is_animated() {
+            self.to_sticker_anim(ctx, &spinner).await
         } else {
-            self.to_sticker_static(ctx).await
+            self.to_sticker_static(ctx, &spinner).await
         }
     }
-    pub async fn to_sticker_batch(ctx: &Context, emotes: &[Emote], par: usize) -> Result<()> {
-        let mut iter = futures::stream::iter(emotes)
-            .map(|emote| emote.to_sticker(ctx))
-            .buffer_unordered(par);
-        while let Some(result) = iter.next().await {
-            if let err @ Err(_) = result {
-                return err;
+    pub async fn to_sticker_batch(
+        ctx: &Context,
+        emotes: &[Emote],
+        par: usize,
+    ) -> Vec<BatchElement<Sticker>> {
+        batch::run(emotes, par, ctx.fail_fast, |emote| async move {
+            BatchElement {
+                id: emote.id.clone(),
+                result: emote.to_sticker(ctx).await,
             }
+        })
+        .await
+    }
+
+    /// Checks the frame durations of an animated emote against the sticker rules
+    pub fn validate_durations(ctx: &Context, id: &EmoteId, info: &WebpInfo) -> Result<(), Error> {
+        if !info.is_animated() {
+            return Ok(());
         }
-        Ok(())
+        Self::check_durations(ctx, id, &info.durations)
     }
 
-    pub async fn new(ctx: &Context, id: EmoteId) -> Result<Self> {
-        // https://github.com/WhatsApp/stickers/blob/main/Android/app/src/main/java/com/example/samplestickerapp/StickerPackValidator.java#L30-L46
-        const ANIMATED_MIN_FRAME_DURATION_MS: i32 = 8;
-        const ANIMATED_MAX_TOTAL_DURATION_MS: i32 = 10_000;
+    /// Records and checks the min frame duration and the total duration rules
+    fn check_durations(ctx: &Context, id: &EmoteId, durations: &[i32]) -> Result<(), Error> {
+        let invalid = |source| Error::Validation {
+            id: id.clone(),
+            source,
+        };
+        let limits = &ctx.settings.limits;
+        let min_duration = durations.iter().copied().min().unwrap_or_default();
+        let total_duration = durations.iter().sum::<i32>();
+        let min_ok = min_duration >= limits.min_frame_duration_ms;
+        let total_ok = total_duration <= limits.max_total_duration_ms;
+
+        let limit = limits.min_frame_duration_ms as i64;
+        let rec = &ctx.recorder;
+        rec.validation(id, "min_frame_duration", min_duration as i64, limit, min_ok);
+        let limit = limits.max_total_duration_ms as i64;
+        rec.validation(
+            id,
+            "max_total_duration",
+            total_duration as i64,
+            limit,
+            total_ok,
+        );
+
+        if !min_ok {
+            Err(invalid(ValidationError::FrameTooShort(min_duration)))
+        } else if !total_ok {
+            Err(invalid(ValidationError::TooLong(total_duration)))
+        } else {
+            Ok(())
+        }
+    }
 
-        Self::download(ctx, id).await?;
+    pub async fn new(ctx: &Context, id: &EmoteId) -> Result<Self, Error> {
+        let spinner = ctx.progress.spinner(id);
 
-        let info = Self::webp_info(ctx, id).await?;
+        spinner.set_message("downloading");
+        Self::download(ctx, id)
+            .await
+            .map_err(|source| Error::Download {
+                id: id.clone(),
+                source,
+            })?;
+
+        spinner.set_message("probing");
+        let info = Self::webp_info(ctx, id)
+            .await
+            .map_err(|source| Error::Probe {
+                id: id.clone(),
+                source,
+            })?;
+
+        spinner.set_message("extracting frames");
+        let raw_frames =
+            Self::extract_frames(ctx, id)
+                .await
+                .map_err(|source| Error::Extraction {
+                    id: id.clone(),
+                    stage: Stage::Extract,
+                    source,
+                })?;
 
         if info.is_animated() {
-            if info.min_duration().unwrap() < ANIMATED_MIN_FRAME_DURATION_MS {
-                return Err(simple_error!("contains too short frames").into());
-            } else if info.total_duration() > ANIMATED_MAX_TOTAL_DURATION_MS {
-                return Err(simple_error!("exceeds maximum duration").into());
-            }
+            let (found, expected) = (raw_frames.files.len(), info.frame_count());
+            ctx.recorder.validation(
+                id,
+                "frame_count",
+                found as i64,
+                expected as i64,
+                found == expected,
+            );
         }
-
-        let raw_frames = Self::extract_frames(ctx, id).await?;
-
         if info.is_animated() && raw_frames.files.len() != info.frame_count() {
-            return Err(simple_error!(
-                "frame counts don't match ({} != {})",
-                raw_frames.files.len(),
-                info.frame_count()
-            )
-            .into());
+            return Err(Error::Validation {
+                id: id.clone(),
+                source: ValidationError::FrameCount(raw_frames.files.len(), info.frame_count()),
+            });
         }
 
-        let resized_frames = Self::resize_frames(ctx, id).await?;
+        let animation = ctx.settings.animation.for_emote(&format!("{id:?}"));
+        let (limits, policy) = (&ctx.settings.limits, ctx.settings.retiming.policy);
+        let invalid = |source| Error::Validation {
+            id: id.clone(),
+            source,
+        };
+        let motion = Self::motion(ctx, id, &info);
+        let timeline = if info.is_animated() {
+            let timeline = Timeline::new(&info.durations)
+                .transform(animation, limits, policy)
+                .map_err(invalid)?;
+            if timeline.durations != info.durations {
+                info!("transformed the animation of emote `{id:?}`");
+            }
+            // the rules were applied by the transform, this only records the outcome
+            Self::check_durations(ctx, id, &timeline.durations)?;
+            timeline
+        } else if motion.is_some() {
+            let frames = animation.motion_frames;
+            Timeline::motion(frames, animation.motion_duration_ms, limits, policy)
+                .and_then(|timeline| timeline.transform(animation, limits, policy))
+                .map_err(invalid)?
+        } else {
+            Timeline::new(&info.durations)
+        };
+
+        spinner.set_message("resizing frames");
+        let resized_frames = Self::resize_frames(ctx, id, &info)
+            .await
+            .map_err(|source| Error::Extraction {
+                id: id.clone(),
+                stage: Stage::Resize,
+                source,
+            })?;
+        let needed = timeline.frames.iter().max().map_or(0, |&last| last + 1);
+        if needed > resized_frames.files.len() {
+            return Err(invalid(ValidationError::FrameCount(
+                resized_frames.files.len(),
+                needed,
+            )));
+        }
 
         Ok(Self {
-            id,
+            id: id.clone(),
             info,
             raw_frames,
             resized_frames,
+            timeline,
         })
     }
-    pub async fn new_batch(ctx: &Context, ids: &[EmoteId], par: usize) -> Vec<BatchElement> {
-        futures::stream::iter(ids)
-            .map(|id| async {
-                BatchElement {
-                    id: *id,
-                    result: Self::new(ctx, *id).await,
-                }
-            })
-            .buffer_unordered(par)
-            .collect::<Vec<_>>()
-            .await
+    pub async fn new_batch(ctx: &Context, ids: &[EmoteId], par: usize) -> Vec<BatchElement<Self>> {
+        batch::run(ids, par, ctx.fail_fast, |id| async move {
+            BatchElement {
+                id: id.clone(),
+                result: Self::new(ctx, id).await,
+            }
+        })
+        .await
     }
 }
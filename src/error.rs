@@ -0,0 +1,255 @@
+use std::fmt::Display;
+use std::process::ExitStatus;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::emote_ext::EmoteId;
+
+/// The step of the pipeline an emote was in when something went wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Download,
+    Probe,
+    Validate,
+    Extract,
+    Resize,
+    Encode,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Stage::Download => "download",
+            Stage::Probe => "probe",
+            Stage::Validate => "validate",
+            Stage::Extract => "extract",
+            Stage::Resize => "resize",
+            Stage::Encode => "encode",
+        })
+    }
+}
+
+/// Failure of one of the external [`Binaries`](crate::binaries::Binaries)
+#[derive(Error, Debug)]
+pub enum ToolError {
+    #[error("couldn't spawn process `{program}`: {source}")]
+    Spawn {
+        program: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("process `{program}` failed with {status}")]
+    Failed {
+        program: String,
+        args: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    #[error("couldn't parse output of process `{program}`: {reason}")]
+    Output { program: String, reason: String },
+}
+
+/// Failure of a request of the [`Client`](crate::download::Client)
+#[derive(Error, Debug)]
+pub enum HttpError {
+    #[error("request to `{url}` failed: {source}")]
+    Request {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("`{url}` responded with {status}")]
+    Status {
+        url: String,
+        status: reqwest::StatusCode,
+    },
+    #[error("`{url}` responded with `{content_type}` instead of an image")]
+    ContentType { url: String, content_type: String },
+    #[error("`{url}` sent more than {max_size} bytes")]
+    TooLarge { url: String, max_size: u64 },
+    #[error("`{url}` wasn't requested because the client is offline")]
+    Offline { url: String },
+}
+
+impl ToolError {
+    pub fn stderr(&self) -> Option<&str> {
+        match self {
+            ToolError::Failed { stderr, .. } => Some(stderr),
+            _ => None,
+        }
+    }
+}
+
+/// Reasons for an emote to be rejected before it is converted
+///
+/// See <https://github.com/WhatsApp/stickers/blob/main/Android/app/src/main/java/com/example/samplestickerapp/StickerPackValidator.java#L30-L46>
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    #[error("contains too short frames ({0}ms)")]
+    FrameTooShort(i32),
+    #[error("exceeds maximum duration ({0}ms)")]
+    TooLong(i32),
+    #[error("frame counts don't match ({0} != {1})")]
+    FrameCount(usize, usize),
+    #[error("doesn't have the sticker dimensions ({0}x{1})")]
+    Dimensions(i32, i32),
+    #[error("`{0}` of the animation broke the rules: {1}")]
+    Transform(&'static str, Box<ValidationError>),
+    #[error("frame range {0}..={1} doesn't fit the {2} frames")]
+    FrameRange(usize, usize, usize),
+    #[error("only {0} stickers of its kind, a pack needs at least {1}")]
+    PackTooSmall(usize, usize),
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("couldn't download emote `{id:?}`: {source}")]
+    Download {
+        id: EmoteId,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("couldn't probe emote `{id:?}`: {source}")]
+    Probe {
+        id: EmoteId,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("emote `{id:?}` is invalid: {source}")]
+    Validation {
+        id: EmoteId,
+        #[source]
+        source: ValidationError,
+    },
+    #[error("couldn't {stage} frames of emote `{id:?}`: {source}")]
+    Extraction {
+        id: EmoteId,
+        stage: Stage,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("couldn't encode emote `{id:?}`: {source}")]
+    Encode {
+        id: EmoteId,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("emote `{id:?}` exceeds the size limit ({size} > {limit} bytes)")]
+    SizeLimit { id: EmoteId, size: u64, limit: u64 },
+    /// The sticker of the emote is missing or unreadable
+    #[error("emote `{id:?}` has no usable sticker: {source}")]
+    NotConverted {
+        id: EmoteId,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("binary `{name}` is missing or unusable: {source}")]
+    ToolMissing {
+        name: &'static str,
+        #[source]
+        source: anyhow::Error,
+    },
+}
+
+impl Error {
+    pub fn id(&self) -> Option<EmoteId> {
+        match self {
+            Error::Download { id, .. }
+            | Error::Probe { id, .. }
+            | Error::Validation { id, .. }
+            | Error::Extraction { id, .. }
+            | Error::Encode { id, .. }
+            | Error::SizeLimit { id, .. }
+            | Error::NotConverted { id, .. } => Some(id.clone()),
+            Error::ToolMissing { .. } => None,
+        }
+    }
+    pub fn stage(&self) -> Option<Stage> {
+        match self {
+            Error::Download { .. } => Some(Stage::Download),
+            Error::Probe { .. } => Some(Stage::Probe),
+            Error::Validation { .. } | Error::NotConverted { .. } => Some(Stage::Validate),
+            Error::Extraction { stage, .. } => Some(*stage),
+            Error::Encode { .. } | Error::SizeLimit { .. } => Some(Stage::Encode),
+            Error::ToolMissing { .. } => None,
+        }
+    }
+    /// Short identifier of the variant, used to group errors in reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Download { .. } => "download",
+            Error::Probe { .. } => "probe",
+            Error::Validation { .. } => "validation",
+            Error::Extraction { .. } => "extraction",
+            Error::Encode { .. } => "encode",
+            Error::SizeLimit { .. } => "size_limit",
+            Error::ToolMissing { .. } => "tool_missing",
+            Error::NotConverted { .. } => "not_converted",
+        }
+    }
+    /// Describes what went wrong without repeating the emote id
+    pub fn reason(&self) -> String {
+        match self {
+            Error::Download { source, .. }
+            | Error::Probe { source, .. }
+            | Error::Extraction { source, .. }
+            | Error::Encode { source, .. }
+            | Error::ToolMissing { source, .. }
+            | Error::NotConverted { source, .. } => source.to_string(),
+            Error::Validation { source, .. } => source.to_string(),
+            Error::SizeLimit { size, limit, .. } => {
+                format!("exceeds the size limit ({size} > {limit} bytes)")
+            }
+        }
+    }
+    /// The stderr of the binary that caused this error, if any
+    pub fn stderr(&self) -> Option<&str> {
+        let source = match self {
+            Error::Download { source, .. }
+            | Error::Probe { source, .. }
+            | Error::Extraction { source, .. }
+            | Error::Encode { source, .. }
+            | Error::ToolMissing { source, .. }
+            | Error::NotConverted { source, .. } => source,
+            Error::Validation { .. } | Error::SizeLimit { .. } => return None,
+        };
+        source
+            .chain()
+            .find_map(|err| err.downcast_ref::<ToolError>()?.stderr())
+    }
+    /// Stable process exit code for this kind of error
+    ///
+    /// | code | meaning      |
+    /// |------|--------------|
+    /// | 1    | other        |
+    /// | 10   | download     |
+    /// | 11   | probe        |
+    /// | 12   | validation   |
+    /// | 13   | extraction   |
+    /// | 14   | encode       |
+    /// | 15   | size limit   |
+    /// | 16   | tool missing |
+    /// | 17   | no sticker   |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Download { .. } => 10,
+            Error::Probe { .. } => 11,
+            Error::Validation { .. } => 12,
+            Error::Extraction { .. } => 13,
+            Error::Encode { .. } => 14,
+            Error::SizeLimit { .. } => 15,
+            Error::ToolMissing { .. } => 16,
+            Error::NotConverted { .. } => 17,
+        }
+    }
+}
+
+/// Exit code for any error returned from `main_`,
+/// see [`Error::exit_code`] for the known ones.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|err| err.downcast_ref::<Error>())
+        .map_or(1, Error::exit_code)
+}
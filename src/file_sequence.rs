@@ -32,9 +32,13 @@ pub enum SequenceError {
 impl TryFrom<DirEntry> for SequenceElement {
     type Error = SequenceError;
     fn try_from(value: DirEntry) -> Result<Self, Self::Error> {
-        let file_name = value.file_name().to_str().unwrap();
+        let lossy = value.file_name().to_string_lossy();
+        let file_name = value
+            .file_name()
+            .to_str()
+            .ok_or_else(|| SequenceError::NoMatch(lossy.to_string()))?;
         let (_, digits) = regex_captures!(r"^(\d+)\.\w{3,4}$", file_name)
-            .ok_or(SequenceError::NoMatch(file_name.to_string()))?;
+            .ok_or_else(|| SequenceError::NoMatch(file_name.to_string()))?;
         let index = digits
             .parse::<usize>()
             .map_err(|_| SequenceError::Parse(file_name.to_string()))?;
@@ -58,7 +62,7 @@ pub fn file_sequence_blocking(path: impl AsRef<Path>) -> Result<FileSequence> {
     files.sort_by_key(|e| e.index);
 
     if files.is_empty() {
-        let path = path.as_ref().to_str().unwrap().to_string();
+        let path = path.as_ref().display().to_string();
         Err(SequenceError::Empty(path).into())
     } else {
         let expected_len = files.last().unwrap().index - files.first().unwrap().index + 1;
@@ -68,7 +72,7 @@ pub fn file_sequence_blocking(path: impl AsRef<Path>) -> Result<FileSequence> {
                 files,
             })
         } else {
-            let path = path.as_ref().to_str().unwrap().to_string();
+            let path = path.as_ref().display().to_string();
             Err(SequenceError::Sequence(path).into())
         }
     }
@@ -1,4 +1,4 @@
-use std::{os::windows::prelude::MetadataExt, path::Path};
+use std::path::Path;
 
 use anyhow::Result;
 use simple_error::simple_error;
@@ -23,5 +23,15 @@ pub async fn is_dir_empty(path: impl AsRef<Path>) -> Result<bool> {
 
 pub async fn file_size(path: impl AsRef<Path>) -> Result<u64> {
     let meta = tokio::fs::metadata(path.as_ref()).await?;
-    Ok(meta.file_size())
+    Ok(meta.len())
+}
+
+/// Removes a file or a directory with its contents, if it exists
+pub async fn remove(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    match tokio::fs::metadata(path).await {
+        Ok(meta) if meta.is_dir() => Ok(tokio::fs::remove_dir_all(path).await?),
+        Ok(_) => Ok(tokio::fs::remove_file(path).await?),
+        Err(_) => Ok(()),
+    }
 }
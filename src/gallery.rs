@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use crate::config::Dirs;
+use crate::report::{EmoteRecord, Report};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 1em; background: #f4f4f4; }
+.controls { margin-bottom: 1em; }
+.grid { display: flex; flex-wrap: wrap; gap: 1em; }
+.card { background: #fff; border-radius: 8px; padding: .5em; box-shadow: 0 1px 3px #0004; }
+.card.warn { outline: 3px solid #e8a317; }
+.card.fail { outline: 3px solid #d0312d; }
+.pair { display: flex; gap: .5em; }
+.pane { width: 256px; height: 256px; display: flex; align-items: center; justify-content: center; }
+.pane img { max-width: 256px; max-height: 256px; }
+.pane .missing { color: #888; }
+body.checker .pane {
+  background-color: #fff;
+  background-image: linear-gradient(45deg, #ccc 25%, transparent 25%),
+    linear-gradient(-45deg, #ccc 25%, transparent 25%),
+    linear-gradient(45deg, transparent 75%, #ccc 75%),
+    linear-gradient(-45deg, transparent 75%, #ccc 75%);
+  background-size: 20px 20px;
+  background-position: 0 0, 0 10px, 10px -10px, -10px 0;
+}
+body.dark .pane { background: #0b141a; }
+body.light .pane { background: #efeae2; }
+.meta { font-size: .85em; margin: .5em 0 0 0; padding-left: 1.2em; }
+.meta .warn { color: #b57600; font-weight: bold; }
+.meta .fail { color: #d0312d; font-weight: bold; }
+";
+
+const CONTROLS: &str = r#"<div class="controls">Background:
+<button onclick="document.body.className='checker'">Checkerboard</button>
+<button onclick="document.body.className='dark'">WhatsApp dark</button>
+<button onclick="document.body.className='light'">WhatsApp light</button>
+</div>"#;
+
+/// One card of the gallery, keyed by the file stem of the sticker
+#[derive(Debug, Default)]
+struct Entry {
+    source: Option<PathBuf>,
+    sticker: Option<PathBuf>,
+    record: Option<EmoteRecord>,
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gif") => "image/gif",
+        Some("png") => "image/png",
+        Some("avif") => "image/avif",
+        Some("svg") => "image/svg+xml",
+        _ => "image/webp",
+    }
+}
+
+async fn data_uri(path: &Path) -> Result<String> {
+    let data = tokio::fs::read(path).await?;
+    Ok(format!(
+        "data:{};base64,{}",
+        mime_type(path),
+        BASE64.encode(data)
+    ))
+}
+
+fn file_stem(path: &Path) -> Option<String> {
+    Some(path.file_stem()?.to_str()?.to_string())
+}
+
+async fn collect(dirs: &Dirs, report: Option<Report>) -> BTreeMap<String, Entry> {
+    let mut entries = BTreeMap::<String, Entry>::new();
+
+    for dir in [&dirs.out_static, &dirs.out_anim] {
+        for file in crate::list_dir::files_with_ext(dir, "webp").await {
+            if let Some(stem) = file_stem(file.path()) {
+                entries.entry(stem).or_default().sticker = Some(file.into_path());
+            }
+        }
+    }
+
+    for record in report.into_iter().flat_map(|report| report.emotes) {
+        let key = format!("{}-{}", record.source, record.id);
+        let entry = entries.entry(key).or_default();
+        if entry.sticker.is_none() {
+            entry.sticker = record.output.clone();
+        }
+        entry.record = Some(record);
+    }
+
+    let downloads = crate::list_dir::files(&dirs.download).await;
+    for file in downloads {
+        if let Some(entry) = file_stem(file.path()).and_then(|stem| entries.get_mut(&stem)) {
+            entry.source = Some(file.into_path());
+        }
+    }
+
+    entries
+}
+
+async fn render_pane(html: &mut String, path: Option<&Path>, label: &str) -> Result<()> {
+    html.push_str(r#"<div class="pane">"#);
+    match path {
+        Some(path) if path.is_file() => write!(
+            html,
+            r#"<img src="{}" title="{label}: {}">"#,
+            data_uri(path).await?,
+            escape(&path.to_string_lossy())
+        )?,
+        _ => write!(html, r#"<span class="missing">no {label}</span>"#)?,
+    }
+    html.push_str("</div>");
+    Ok(())
+}
+
+fn render_meta(html: &mut String, name: &str, entry: &Entry) -> Result<()> {
+    write!(html, "<ul class=\"meta\"><li><b>{}</b>", escape(name))?;
+    let Some(record) = &entry.record else {
+        html.push_str("</li></ul>");
+        return Ok(());
+    };
+    write!(
+        html,
+        " ({}, {})</li>",
+        escape(&record.source),
+        escape(&record.status)
+    )?;
+
+    if let Some(size) = record.size {
+        write!(html, "<li>size: {:.1} KiB</li>", size as f64 / 1024.0)?;
+    }
+    if let Some(preset) = record.preset {
+        write!(
+            html,
+            "<li>quality: {} (method {})</li>",
+            preset.quality, preset.level
+        )?;
+    }
+    if let Some(info) = &record.info {
+        write!(
+            html,
+            "<li>{}x{}, {} frames, {} ms</li>",
+            info.width, info.height, info.frame_count, info.total_duration_ms
+        )?;
+    }
+    for validation in record.validation.iter().filter(|v| !v.passed) {
+        write!(
+            html,
+            "<li class=\"warn\">{}: {} (limit {})</li>",
+            escape(&validation.rule),
+            validation.value,
+            validation.limit
+        )?;
+    }
+    if let Some(degraded) = &record.degraded {
+        write!(html, "<li class=\"warn\">{}</li>", escape(degraded))?;
+    }
+    if let Some(error) = &record.error {
+        write!(
+            html,
+            "<li class=\"fail\">{}: {}</li>",
+            escape(&error.kind),
+            escape(&error.message)
+        )?;
+    }
+    html.push_str("</ul>");
+    Ok(())
+}
+
+fn card_class(entry: &Entry) -> &'static str {
+    match &entry.record {
+        Some(record) if record.error.is_some() => "card fail",
+        Some(record)
+            if record.degraded.is_some() || record.validation.iter().any(|v| !v.passed) =>
+        {
+            "card warn"
+        }
+        _ => "card",
+    }
+}
+
+/// Renders a self-contained HTML page with every converted sticker next to its source
+pub async fn render(dirs: &Dirs, report: Option<Report>) -> Result<String> {
+    let entries = collect(dirs, report).await;
+
+    let mut html = String::new();
+    write!(
+        html,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+        <title>convertoid gallery</title><style>{STYLE}</style></head>\
+        <body class=\"checker\">{CONTROLS}<div class=\"grid\">"
+    )?;
+
+    for (name, entry) in &entries {
+        write!(
+            html,
+            "<div class=\"{}\"><div class=\"pair\">",
+            card_class(entry)
+        )?;
+        render_pane(&mut html, entry.source.as_deref(), "source").await?;
+        render_pane(&mut html, entry.sticker.as_deref(), "sticker").await?;
+        html.push_str("</div>");
+        render_meta(&mut html, name, entry)?;
+        html.push_str("</div>");
+    }
+
+    html.push_str("</div></body></html>\n");
+    Ok(html)
+}
+
+pub async fn write(dirs: &Dirs, report: Option<&Path>, output: &Path) -> Result<()> {
+    let report = match report {
+        Some(path) => Some(Report::read_from(path).await?),
+        None => None,
+    };
+    let html = render(dirs, report).await?;
+    Ok(tokio::fs::write(output, html).await?)
+}
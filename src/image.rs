@@ -0,0 +1,65 @@
+//! Reading and writing the PNG frames that the pipeline works on
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+/// Frame with 8 bit RGBA pixels that aren't premultiplied
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+/// Reads a PNG of any color type as RGBA
+pub fn read_png(path: &Path) -> Result<Image> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|px| [px[0], px[1], px[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|px| [px[0], px[0], px[0], px[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+        png::ColorType::Indexed => return Err(anyhow!("`{path:?}` wasn't expanded to RGBA")),
+    };
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        rgba,
+    })
+}
+
+pub fn encode_png(image: &Image, out: impl std::io::Write) -> Result<()> {
+    let mut encoder = png::Encoder::new(out, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.rgba)?;
+    Ok(writer.finish()?)
+}
+
+/// Bytes of the image encoded as PNG
+pub fn png_size(image: &Image) -> Result<u64> {
+    let mut buf = Vec::new();
+    encode_png(image, &mut buf)?;
+    Ok(buf.len() as u64)
+}
+
+pub fn write_png(image: &Image, path: &Path) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    encode_png(image, &mut out)?;
+    Ok(std::io::Write::flush(&mut out)?)
+}
@@ -0,0 +1,283 @@
+//! Takes emote files from a folder or a zip archive, e.g. someone else's download directory
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+use crate::batch::{Outcome, Summary};
+use crate::cache::CacheEntry;
+use crate::context::Context;
+use crate::emote_ext::EmoteId;
+use crate::error::Error;
+use crate::source::{Format, Sources};
+
+const FORMATS: [Format; 4] = [Format::Webp, Format::Gif, Format::Png, Format::Avif];
+
+/// A file of the folder or archive, `path` is relative to its root
+/// and `index` is its position in the archive
+#[derive(Clone)]
+struct File {
+    path: PathBuf,
+    index: usize,
+}
+
+type Archive = ZipArchive<std::fs::File>;
+
+/// A folder or a `.zip` archive whose files are read one at a time
+#[derive(Clone)]
+enum Bundle {
+    Dir(PathBuf),
+    Zip(Arc<Mutex<Archive>>),
+}
+
+fn is_wanted(path: &Path) -> bool {
+    let is_sidecar = path.to_string_lossy().ends_with(".http.json");
+    let is_image = path
+        .extension()
+        .is_some_and(|ext| FORMATS.iter().any(|format| ext == format.extension()));
+    is_sidecar || is_image
+}
+
+/// Reads at most `max_size` bytes, fails instead of truncating a larger file
+fn read_limited(reader: impl Read, path: &Path, max_size: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(max_size + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > max_size {
+        return Err(anyhow!("`{path:?}` is larger than {max_size} bytes"));
+    }
+    Ok(data)
+}
+
+impl Bundle {
+    fn open_blocking(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            Ok(Bundle::Dir(path.to_path_buf()))
+        } else if path.extension().is_some_and(|ext| ext == "zip") {
+            let zip = ZipArchive::new(std::fs::File::open(path)?)?;
+            Ok(Bundle::Zip(Arc::new(Mutex::new(zip))))
+        } else {
+            Err(anyhow!(
+                "`{path:?}` is neither a folder nor a `.zip` archive"
+            ))
+        }
+    }
+
+    fn lock(zip: &Mutex<Archive>) -> MutexGuard<'_, Archive> {
+        zip.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The emote files and sidecars, without their content
+    fn list_blocking(&self) -> Result<Vec<File>> {
+        let mut files = Vec::new();
+        match self {
+            Bundle::Dir(root) => {
+                for entry in WalkDir::new(root).min_depth(1) {
+                    let entry = entry?;
+                    let path = entry.path().strip_prefix(root)?.to_path_buf();
+                    if entry.file_type().is_file() && is_wanted(&path) {
+                        files.push(File { path, index: 0 });
+                    }
+                }
+            }
+            Bundle::Zip(zip) => {
+                let mut zip = Self::lock(zip);
+                for index in 0..zip.len() {
+                    let entry = zip.by_index(index)?;
+                    let Some(path) = entry.enclosed_name().map(Path::to_path_buf) else {
+                        warn!("skipping `{}`, it points outside the archive", entry.name());
+                        continue;
+                    };
+                    if entry.is_file() && is_wanted(&path) {
+                        files.push(File { path, index });
+                    }
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn read_blocking(&self, file: &File, max_size: u64) -> Result<Vec<u8>> {
+        match self {
+            Bundle::Dir(root) => {
+                let reader = std::fs::File::open(root.join(&file.path))?;
+                read_limited(reader, &file.path, max_size)
+            }
+            Bundle::Zip(zip) => {
+                let mut zip = Self::lock(zip);
+                let entry = zip.by_index(file.index)?;
+                read_limited(entry, &file.path, max_size)
+            }
+        }
+    }
+
+    async fn open(path: &Path) -> Result<Self> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || Self::open_blocking(&path)).await?
+    }
+
+    async fn list(&self) -> Result<Vec<File>> {
+        let bundle = self.clone();
+        tokio::task::spawn_blocking(move || bundle.list_blocking()).await?
+    }
+
+    /// Reads the content of a file, failing if it's larger than `max_size`
+    async fn read(&self, file: &File, max_size: u64) -> Result<Vec<u8>> {
+        let bundle = self.clone();
+        let file = file.clone();
+        tokio::task::spawn_blocking(move || bundle.read_blocking(&file, max_size)).await?
+    }
+}
+
+/// Tells which emote a file is.
+///
+/// Files named `<source>-<id>`, like the downloads, carry their source.
+/// Otherwise the source is taken from the parent folder if it's named like a source,
+/// then from `source` and finally from the URL in the sidecar.
+fn identify(
+    sources: &Sources,
+    path: &Path,
+    source: Option<&str>,
+    entry: Option<&CacheEntry>,
+) -> Result<EmoteId> {
+    let id = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| anyhow!("file name isn't valid UTF-8"))?;
+    let prefixed = id.split_once('-').filter(|(name, rest)| {
+        sources
+            .get(name)
+            .is_ok_and(|source| source.parse_id(rest).is_ok())
+    });
+    if let Some((name, id)) = prefixed {
+        return sources.parse(name, id);
+    }
+
+    let folder = path
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .and_then(|name| name.to_str())
+        .filter(|name| sources.get(name).is_ok());
+    let from_sidecar = || {
+        let entry = entry?;
+        sources.names().into_iter().find(|&name| {
+            sources
+                .get(name)
+                .is_ok_and(|source| source.url(id, entry.rendition) == entry.url)
+        })
+    };
+    let name = folder.or(source).or_else(from_sidecar).ok_or_else(|| {
+        anyhow!("unknown source, put it in a `<source>/` folder or pass `--source`")
+    })?;
+    sources.parse(name, id)
+}
+
+/// Reads the sidecars of the bundle and tells which emote each of its images is,
+/// images that can't be identified are skipped
+async fn scan(
+    ctx: &Context,
+    bundle: &Bundle,
+    path: &Path,
+    source: Option<&str>,
+) -> Result<Vec<(File, EmoteId, Option<CacheEntry>)>> {
+    if let Some(name) = source {
+        ctx.sources.get(name)?;
+    }
+    let max_size = ctx.client.max_body_size();
+
+    let mut sidecars = HashMap::new();
+    let mut images = Vec::new();
+    for file in bundle.list().await? {
+        let name = file.path.to_string_lossy().into_owned();
+        let Some(image) = name.strip_suffix(".http.json") else {
+            images.push(file);
+            continue;
+        };
+        let entry = bundle.read(&file, max_size).await.and_then(|data| {
+            serde_json::from_slice::<CacheEntry>(&data).map_err(anyhow::Error::from)
+        });
+        match entry {
+            Ok(entry) => {
+                sidecars.insert(PathBuf::from(image), entry);
+            }
+            Err(err) => warn!("ignoring sidecar `{name}`: {err}"),
+        }
+    }
+    if images.is_empty() {
+        return Err(anyhow!("`{path:?}` doesn't contain any emote files"));
+    }
+
+    let mut identified = Vec::with_capacity(images.len());
+    for file in images {
+        let entry = sidecars.remove(&file.path);
+        match identify(&ctx.sources, &file.path, source, entry.as_ref()) {
+            Ok(id) => identified.push((file, id, entry)),
+            Err(err) => warn!("skipping `{:?}`: {err}", file.path),
+        }
+    }
+    Ok(identified)
+}
+
+/// The emote files of the folder or archive at `path` and which emotes they'd be imported as
+pub async fn plan(
+    ctx: &Context,
+    path: &Path,
+    source: Option<&str>,
+) -> Result<Vec<(PathBuf, EmoteId)>> {
+    let bundle = Bundle::open(path).await?;
+    let files = scan(ctx, &bundle, path, source).await?;
+    Ok(files
+        .into_iter()
+        .map(|(file, id, _)| (file.path, id))
+        .collect())
+}
+
+/// Registers every emote file of the folder or archive at `path` in the download directory.
+///
+/// The files are read one at a time. Emotes whose download changed lose their frames
+/// and stickers.
+pub async fn import(ctx: &Context, path: &Path, source: Option<&str>) -> Result<Summary> {
+    let bundle = Bundle::open(path).await?;
+    let max_size = ctx.client.max_body_size();
+
+    let mut summary = Summary::default();
+    for (file, id, entry) in scan(ctx, &bundle, path, source).await? {
+        let result = async {
+            let data = bundle.read(&file, max_size).await?;
+            ctx.import_emote(&id, &file.path, &data, entry).await
+        };
+        let result = result.await.map_err(|source| Error::Download {
+            id: id.clone(),
+            source,
+        });
+        match result {
+            Ok(changed) => {
+                if changed {
+                    ctx.invalidate(&id).await?;
+                    info!("imported emote `{id:?}` from `{:?}`", file.path);
+                } else {
+                    info!("emote `{id:?}` was already imported");
+                }
+                let dl_path = ctx.download_path(&id);
+                let size = tokio::fs::metadata(&dl_path).await.ok().map(|m| m.len());
+                summary.push(
+                    id,
+                    Outcome::Succeeded {
+                        path: dl_path,
+                        size,
+                    },
+                );
+            }
+            Err(err) => {
+                warn!("couldn't import `{:?}`: {err}", file.path);
+                summary.push_error(id, err);
+            }
+        }
+    }
+    Ok(summary)
+}
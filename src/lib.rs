@@ -0,0 +1,37 @@
+//! Converts emotes from 7TV and BTTV to WhatsApp stickers.
+//!
+//! Build a [`Context`] with [`ContextBuilder`], then run single emotes through
+//! [`pipeline::convert_one`] or whole batches through the other functions of [`pipeline`].
+
+pub mod alpha;
+pub mod animation;
+pub mod batch;
+pub mod binaries;
+pub mod cache;
+pub mod config;
+pub mod context;
+pub mod convert;
+pub mod download;
+pub mod effects;
+pub mod emote;
+pub mod emote_ext;
+pub mod error;
+pub mod file_sequence;
+pub mod fs;
+pub mod gallery;
+pub mod image;
+pub mod import;
+pub mod list_dir;
+pub mod motion;
+pub mod pipeline;
+pub mod progress;
+pub mod report;
+pub mod source;
+pub mod unwrap_ext;
+pub mod webp;
+
+pub use crate::context::{Context, ContextBuilder};
+pub use crate::emote::Sticker;
+pub use crate::emote_ext::EmoteId;
+pub use crate::error::Error;
+pub use crate::pipeline::StickerResult;
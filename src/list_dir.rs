@@ -15,10 +15,10 @@ where
         .unwrap_or(false)
 }
 fn is_file(entry: &DirEntry) -> bool {
-    entry.metadata().map_or(false, |meta| meta.is_dir())
+    entry.metadata().is_ok_and(|meta| meta.is_file())
 }
 
-/// Collects any file or folder with an extension by
+/// Collects any file with an extension by
 /// [`Path::extension`](std::path::Path::extension) non recursive.
 pub fn files_with_ext_blocking<P, Q>(path: P, ext: Q) -> Vec<DirEntry>
 where
@@ -28,8 +28,8 @@ where
     let walk = WalkDir::new(path.as_ref()).max_depth(1).min_depth(1);
     let buffer = walk
         .into_iter()
-        .filter_entry(|entry| has_ext(entry, ext.as_ref()) && is_file(entry))
         .filter_map(|entry| entry.ok())
+        .filter(|entry| has_ext(entry, ext.as_ref()) && is_file(entry))
         .collect::<Vec<_>>();
 
     if buffer.is_empty() {
@@ -50,3 +50,34 @@ where
         .await
         .unwrap()
 }
+
+/// Collects any file or folder non recursive.
+pub fn entries_blocking(path: impl AsRef<Path>) -> Vec<DirEntry> {
+    WalkDir::new(path.as_ref())
+        .max_depth(1)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .collect()
+}
+
+pub async fn entries(path: impl AsRef<Path>) -> Vec<DirEntry> {
+    let path = path.as_ref().to_owned();
+    tokio::task::spawn_blocking(move || entries_blocking(path))
+        .await
+        .unwrap()
+}
+
+/// Collects any file non recursive.
+pub fn files_blocking(path: impl AsRef<Path>) -> Vec<DirEntry> {
+    let mut entries = entries_blocking(path);
+    entries.retain(is_file);
+    entries
+}
+
+pub async fn files(path: impl AsRef<Path>) -> Vec<DirEntry> {
+    let path = path.as_ref().to_owned();
+    tokio::task::spawn_blocking(move || files_blocking(path))
+        .await
+        .unwrap()
+}
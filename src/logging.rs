@@ -1,15 +1,41 @@
 use anyhow::Result;
-use log::LevelFilter;
+use indicatif::MultiProgress;
+use log::{LevelFilter, Log, Metadata, Record};
 use simplelog::{ColorChoice, ConfigBuilder, TermLogger, TerminalMode};
 
-pub fn init() -> Result<()> {
-    TermLogger::init(
+/// Hides the progress bars while a line is logged so it ends up above them
+struct ProgressLogger {
+    inner: Box<TermLogger>,
+    multi: MultiProgress,
+}
+
+impl Log for ProgressLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.multi.suspend(|| self.inner.log(record));
+        }
+    }
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+pub fn init(multi: &MultiProgress) -> Result<()> {
+    let inner = TermLogger::new(
         LevelFilter::Trace,
         ConfigBuilder::default()
             .add_filter_allow_str("convertoid")
             .build(),
         TerminalMode::Mixed,
         ColorChoice::Auto,
-    )?;
+    );
+    log::set_boxed_logger(Box::new(ProgressLogger {
+        inner,
+        multi: multi.clone(),
+    }))?;
+    log::set_max_level(LevelFilter::Trace);
     Ok(())
 }
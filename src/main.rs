@@ -1,52 +1,103 @@
-#![allow(dead_code, unreachable_code, unused_variables)]
-
-mod binaries;
-mod context;
-mod convert;
-mod download;
-mod emote;
-mod emote_ext;
-mod file_sequence;
-mod fs;
-mod list_dir;
+mod doctor;
 mod logging;
 mod opt;
-mod unwrap_ext;
-mod webp;
+mod plan;
 
-use crate::context::Context;
-use crate::emote::Emote;
+use crate::opt::{Command, Opt};
 
 use anyhow::Result;
-use log::warn;
+use convertoid::batch::Summary;
+use convertoid::config::Settings;
+use convertoid::emote_ext::EmoteId;
+use convertoid::progress::Progress;
+use convertoid::report::Report;
+use convertoid::{error, gallery, import, pipeline, Context};
+use log::{error, info};
+use structopt::StructOpt;
 
-async fn main_() -> Result<()> {
-    logging::init()?;
+/// Runs the given stage(s) for every emote id
+async fn run(ctx: &Context, cmd: &Command, ids: &[EmoteId]) -> Result<Summary> {
+    match cmd {
+        Command::Download => Ok(pipeline::download(ctx, ids).await),
+        Command::Inspect => Ok(pipeline::inspect(ctx, ids).await),
+        Command::Extract => Ok(pipeline::extract(ctx, ids).await),
+        Command::Convert => Ok(pipeline::convert(ctx, ids).await),
+        Command::Validate => Ok(pipeline::validate(ctx, ids).await),
+        Command::Pack {
+            name,
+            publisher,
+            emoji,
+            output,
+        } => pipeline::pack(ctx, ids, name, publisher, emoji, output).await,
+        Command::Clean { all } => pipeline::clean(ctx, ids, *all).await,
+        Command::Import { path, source } => import::import(ctx, path, source.as_deref()).await,
+        Command::Gallery { .. } => unreachable!("the gallery doesn't need a context"),
+        Command::Doctor => unreachable!("the doctor runs without a context"),
+    }
+}
+
+async fn main_() -> Result<i32> {
+    let progress = Progress::new();
+    logging::init(progress.multi())?;
+
+    let opt = Opt::from_args();
+    let mut settings = Settings::load(opt.profile.as_deref(), opt.config.as_deref())?;
+    opt.apply_to(&mut settings);
+    settings.validate()?;
+    if let Some(Command::Doctor) = &opt.cmd {
+        return Ok(doctor::run(&settings, &opt.sources(), opt.offline).await);
+    }
+    settings.create_dirs()?;
+
+    if let Some(Command::Gallery { report, output }) = &opt.cmd {
+        gallery::write(&settings.dirs, report.as_deref(), output).await?;
+        info!("wrote gallery to `{output:?}`");
+        return Ok(0);
+    }
+
+    let sources = opt.sources();
+    let ids = opt.to_emote_ids(&sources)?;
+    let ctx = Context::builder()
+        .settings(settings)
+        .sources(sources)
+        .progress(progress)
+        .fail_fast(opt.fail_fast)
+        .refresh(opt.refresh)
+        .offline(opt.offline)
+        .build()?;
+    let cmd = opt.cmd.as_ref().unwrap_or(&Command::Convert);
+    if opt.dry_run {
+        plan::print(&ctx, cmd, &ids).await?;
+        return Ok(0);
+    }
 
-    let ctx = Context::new()?;
-    let _ = ctx.bin.check(3).await?;
+    let needed = cmd.needed_binaries(&ctx.settings);
+    let _ = ctx.bin.check(3, &needed).await?;
 
-    let ids = ctx.to_emote_ids();
+    let started = chrono::Utc::now();
+    let summary = run(&ctx, cmd, &ids).await?;
 
-    let processed = Emote::new_batch(&ctx, &ids, 5)
-        .await
-        .into_iter()
-        .filter_map(|batch| {
-            if let Err(e) = &batch.result {
-                warn!("couldn't process `{:?}`: {}", batch.id, e);
-                None
-            } else {
-                Some(batch.result.unwrap())
-            }
-        })
-        .collect::<Vec<_>>();
+    ctx.progress.finish();
+    summary.print();
 
-    Emote::to_sticker_batch(&ctx, &processed, 14).await?;
+    if let Some(path) = &opt.report {
+        Report::new(started, &summary, &ctx.recorder)
+            .write_to(path)
+            .await?;
+        info!("wrote report to `{path:?}`");
+    }
 
-    Ok(())
+    Ok(summary.exit_code())
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    main_().await.unwrap();
+    match main_().await {
+        Ok(0) => {}
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            error!("{err}");
+            std::process::exit(error::exit_code(&err));
+        }
+    }
 }
@@ -0,0 +1,300 @@
+//! Animates static images with motion presets like shake or spin
+
+use std::f32::consts::{PI, TAU};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::config::Motion;
+use crate::image::{read_png, write_png, Image};
+
+/// Where the image is drawn in one frame, relative to the center of the canvas
+struct Pose {
+    angle: f32,
+    scale: f32,
+    offset: (f32, f32),
+    /// Rotation of the hue in radians
+    hue: f32,
+}
+
+impl Pose {
+    const IDENTITY: Pose = Pose {
+        angle: 0.0,
+        scale: 1.0,
+        offset: (0.0, 0.0),
+        hue: 0.0,
+    };
+}
+
+/// The pose at `t` in `0..1`, every preset but `Zoom` ends where it starts so it loops smoothly
+fn pose(motion: Motion, t: f32, size: f32) -> Pose {
+    match motion {
+        Motion::Shake => Pose {
+            angle: 0.05 * (TAU * 5.0 * t).sin(),
+            scale: 0.9,
+            offset: (
+                0.03 * size * (TAU * 4.0 * t).sin(),
+                0.03 * size * (TAU * 3.0 * t).cos(),
+            ),
+            ..Pose::IDENTITY
+        },
+        // small enough that the corners stay inside when it's turned by 45°
+        Motion::Spin => Pose {
+            angle: TAU * t,
+            scale: 0.7,
+            ..Pose::IDENTITY
+        },
+        Motion::Bounce => Pose {
+            scale: 0.85,
+            offset: (0.0, 0.06 * size - 0.12 * size * (PI * t).sin().abs()),
+            ..Pose::IDENTITY
+        },
+        Motion::Pulse => Pose {
+            scale: 0.95 - 0.05 * (TAU * t).cos(),
+            ..Pose::IDENTITY
+        },
+        Motion::Rainbow => Pose {
+            hue: TAU * t,
+            ..Pose::IDENTITY
+        },
+        Motion::Zoom => Pose {
+            scale: 1.0 + 2.0 * t * t,
+            ..Pose::IDENTITY
+        },
+    }
+}
+
+/// Premultiplied color at a position between pixel centers, transparent outside the image
+fn sample(image: &Image, x: f32, y: f32) -> [f32; 4] {
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let mut out = [0.0; 4];
+    for (dx, dy, weight) in [
+        (0, 0, (1.0 - fx) * (1.0 - fy)),
+        (1, 0, fx * (1.0 - fy)),
+        (0, 1, (1.0 - fx) * fy),
+        (1, 1, fx * fy),
+    ] {
+        let (px, py) = (x0 as i64 + dx, y0 as i64 + dy);
+        if px < 0 || py < 0 || px >= image.width as i64 || py >= image.height as i64 {
+            continue;
+        }
+        let index = (py as usize * image.width + px as usize) * 4;
+        let alpha = image.rgba[index + 3] as f32 / 255.0;
+        for (out, &value) in out.iter_mut().zip(&image.rgba[index..index + 3]) {
+            *out += weight * alpha * value as f32;
+        }
+        out[3] += weight * alpha;
+    }
+    out
+}
+
+/// Rotates the hue of a color around the gray axis
+fn rotate_hue(rgb: [f32; 3], angle: f32) -> [f32; 3] {
+    let (sin, cos) = angle.sin_cos();
+    let third = (1.0 - cos) / 3.0;
+    let root = (1.0f32 / 3.0).sqrt() * sin;
+    let [r, g, b] = rgb;
+    [
+        r * (cos + third) + g * (third - root) + b * (third + root),
+        r * (third + root) + g * (cos + third) + b * (third - root),
+        r * (third - root) + g * (third + root) + b * (cos + third),
+    ]
+}
+
+fn render(image: &Image, pose: &Pose) -> Image {
+    let (width, height) = (image.width, image.height);
+    let center = (width as f32 / 2.0, height as f32 / 2.0);
+    let (sin, cos) = (-pose.angle).sin_cos();
+    let mut rgba = vec![0; width * height * 4];
+    for (index, out) in rgba.chunks_exact_mut(4).enumerate() {
+        // maps the output pixel back onto the image
+        let x = (index % width) as f32 + 0.5 - center.0 - pose.offset.0;
+        let y = (index / width) as f32 + 0.5 - center.1 - pose.offset.1;
+        let sx = (x * cos - y * sin) / pose.scale + center.0;
+        let sy = (x * sin + y * cos) / pose.scale + center.1;
+
+        let [r, g, b, a] = sample(image, sx, sy);
+        if a <= 0.0 {
+            continue;
+        }
+        let rgb = [r / a, g / a, b / a];
+        let rgb = match pose.hue {
+            hue if hue != 0.0 => rotate_hue(rgb, hue),
+            _ => rgb,
+        };
+        for (out, channel) in out.iter_mut().zip(rgb) {
+            *out = channel.round().clamp(0.0, 255.0) as u8;
+        }
+        out[3] = (a * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    Image {
+        width,
+        height,
+        rgba,
+    }
+}
+
+fn generate_blocking(src: &Path, dst: &Path, motion: Motion, frames: usize) -> Result<()> {
+    let image = read_png(src)?;
+    for index in 0..frames {
+        let t = index as f32 / frames as f32;
+        let frame = render(&image, &pose(motion, t, image.width as f32));
+        write_png(&frame, &dst.join(format!("{index:04}.png")))?;
+    }
+    Ok(())
+}
+
+/// Writes `frames` frames of the image moving like `motion` to `dst` as `0000.png` and so on
+pub async fn generate(src: PathBuf, dst: PathBuf, motion: Motion, frames: usize) -> Result<()> {
+    tokio::task::spawn_blocking(move || generate_blocking(&src, &dst, motion, frames)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Motion; 6] = [
+        Motion::Shake,
+        Motion::Spin,
+        Motion::Bounce,
+        Motion::Pulse,
+        Motion::Rainbow,
+        Motion::Zoom,
+    ];
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    /// Checks the angle, scale, offset and hue of the pose
+    fn assert_pose(pose: Pose, angle: f32, scale: f32, offset: (f32, f32), hue: f32) {
+        let what = format!(
+            "angle {} scale {} offset {:?} hue {}",
+            pose.angle, pose.scale, pose.offset, pose.hue
+        );
+        assert!(close(pose.angle, angle), "{what}");
+        assert!(close(pose.scale, scale), "{what}");
+        assert!(close(pose.offset.0, offset.0), "{what}");
+        assert!(close(pose.offset.1, offset.1), "{what}");
+        assert!(close(pose.hue, hue), "{what}");
+    }
+
+    #[test]
+    fn poses_at_the_start_and_the_middle() {
+        let size = 100.0;
+        assert_pose(pose(Motion::Shake, 0.0, size), 0.0, 0.9, (0.0, 3.0), 0.0);
+        assert_pose(pose(Motion::Shake, 0.5, size), 0.0, 0.9, (0.0, -3.0), 0.0);
+        assert_pose(pose(Motion::Spin, 0.0, size), 0.0, 0.7, (0.0, 0.0), 0.0);
+        assert_pose(pose(Motion::Spin, 0.5, size), PI, 0.7, (0.0, 0.0), 0.0);
+        assert_pose(pose(Motion::Bounce, 0.0, size), 0.0, 0.85, (0.0, 6.0), 0.0);
+        assert_pose(pose(Motion::Bounce, 0.5, size), 0.0, 0.85, (0.0, -6.0), 0.0);
+        assert_pose(pose(Motion::Pulse, 0.0, size), 0.0, 0.9, (0.0, 0.0), 0.0);
+        assert_pose(pose(Motion::Pulse, 0.5, size), 0.0, 1.0, (0.0, 0.0), 0.0);
+        assert_pose(pose(Motion::Rainbow, 0.0, size), 0.0, 1.0, (0.0, 0.0), 0.0);
+        assert_pose(pose(Motion::Rainbow, 0.5, size), 0.0, 1.0, (0.0, 0.0), PI);
+        assert_pose(pose(Motion::Zoom, 0.0, size), 0.0, 1.0, (0.0, 0.0), 0.0);
+        assert_pose(pose(Motion::Zoom, 0.5, size), 0.0, 1.5, (0.0, 0.0), 0.0);
+    }
+
+    /// An asymmetric image, so rotations and offsets show
+    fn arrow() -> Image {
+        let size = 16;
+        let mut rgba = vec![0; size * size * 4];
+        for (index, px) in rgba.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (index % size, index / size);
+            if (4..12).contains(&y) && x >= 3 && x < 3 + (y - 4) * 2 {
+                px.copy_from_slice(&[(x * 16) as u8, 40, (y * 16) as u8, 255]);
+            }
+        }
+        Image {
+            width: size,
+            height: size,
+            rgba,
+        }
+    }
+
+    fn max_difference(a: &Image, b: &Image) -> u8 {
+        a.rgba
+            .iter()
+            .zip(&b.rgba)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn presets_loop() {
+        // the frame after the last one is the first one again
+        let image = arrow();
+        for motion in ALL.into_iter().filter(|&motion| motion != Motion::Zoom) {
+            let first = render(&image, &pose(motion, 0.0, 16.0));
+            let wrapped = render(&image, &pose(motion, 1.0, 16.0));
+            assert!(max_difference(&first, &wrapped) <= 1, "{motion:?}");
+        }
+    }
+
+    #[test]
+    fn full_hue_rotation_is_the_identity() {
+        for rgb in [
+            [255.0, 0.0, 0.0],
+            [12.0, 200.0, 90.0],
+            [128.0, 128.0, 128.0],
+        ] {
+            let rotated = rotate_hue(rgb, TAU);
+            for (a, b) in rotated.into_iter().zip(rgb) {
+                assert!((a - b).abs() < 0.01, "{rgb:?} -> {rotated:?}");
+            }
+        }
+        // gray has no hue
+        let gray = rotate_hue([128.0; 3], PI);
+        assert!(gray.iter().all(|&c| (c - 128.0).abs() < 0.01));
+    }
+
+    #[test]
+    fn zoom_stays_centred() {
+        // a centred square grows around the center
+        let size = 16;
+        let mut rgba = vec![0; size * size * 4];
+        for (index, px) in rgba.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (index % size, index / size);
+            if (6..10).contains(&x) && (6..10).contains(&y) {
+                px.copy_from_slice(&[255, 255, 255, 255]);
+            }
+        }
+        let image = Image {
+            width: size,
+            height: size,
+            rgba,
+        };
+        let zoomed = render(&image, &pose(Motion::Zoom, 0.5, size as f32));
+        let alpha = |x: usize, y: usize| zoomed.rgba[(y * size + x) * 4 + 3];
+        for y in 0..size {
+            for x in 0..size {
+                // up to rounding
+                assert!(alpha(x, y).abs_diff(alpha(size - 1 - x, y)) <= 1, "{x},{y}");
+                assert!(alpha(x, y).abs_diff(alpha(x, size - 1 - y)) <= 1, "{x},{y}");
+            }
+        }
+        // 1.5 times as large, so it reaches into the pixels next to it
+        assert_eq!(alpha(6, 8), 255);
+        assert!(alpha(5, 8) > 0);
+        assert_eq!(alpha(3, 8), 0);
+    }
+
+    #[test]
+    fn sampler_interpolates() {
+        let mut image = arrow();
+        image.rgba.fill(0);
+        image.rgba[..8].copy_from_slice(&[200, 0, 0, 255, 0, 0, 100, 255]);
+        // pixel centers are exact
+        assert_eq!(sample(&image, 0.5, 0.5), [200.0, 0.0, 0.0, 1.0]);
+        // halfway between the two pixels
+        assert_eq!(sample(&image, 1.0, 0.5), [100.0, 0.0, 50.0, 1.0]);
+        // halfway to the transparent row below, premultiplied
+        assert_eq!(sample(&image, 0.5, 1.0), [100.0, 0.0, 0.0, 0.5]);
+        // outside of the image
+        assert_eq!(sample(&image, -3.0, 0.5), [0.0; 4]);
+    }
+}
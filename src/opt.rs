@@ -1,10 +1,14 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use anyhow::Result;
 use lazy_regex::lazy_regex;
 use structopt::StructOpt;
 use thiserror::Error;
 
-use crate::emote_ext::{BttvId, EmoteIdExt, SevenTvId};
+use convertoid::binaries;
+use convertoid::config::{Motion, Scaler, Settings};
+use convertoid::emote_ext::EmoteId;
+use convertoid::source::{Fixture, Format, Sources};
 
 #[derive(Error, Debug)]
 pub enum DirPathParseError {
@@ -22,26 +26,27 @@ pub enum FilePathParseError {
     InvalidType,
 }
 
-fn parse_dir_path(src: &str) -> Result<PathBuf, DirPathParseError> {
-    let path = PathBuf::from(src);
+/// Creates the directory if it doesn't exist yet
+pub fn ensure_dir(path: &Path) -> Result<(), DirPathParseError> {
     match path.metadata() {
         Ok(meta) => {
             if !meta.is_dir() {
                 Err(DirPathParseError::InvalidType)
             } else {
-                Ok(path)
-            }
-        }
-        Err(_) => {
-            if let Err(err) = std::fs::create_dir(&path) {
-                Err(DirPathParseError::Create(err))
-            } else {
-                Ok(path)
+                Ok(())
             }
         }
+        Err(_) => std::fs::create_dir(path).map_err(DirPathParseError::Create),
     }
 }
 
+fn parse_dir_path(src: &str) -> Result<PathBuf, DirPathParseError> {
+    let path = PathBuf::from(src);
+    ensure_dir(&path)?;
+    Ok(path)
+}
+
+#[allow(dead_code)]
 fn parse_file_path(src: &str) -> Result<PathBuf, FilePathParseError> {
     let path = PathBuf::from(src);
     let meta = path.metadata()?;
@@ -52,7 +57,7 @@ fn parse_file_path(src: &str) -> Result<PathBuf, FilePathParseError> {
     }
 }
 
-#[allow(clippy::enum_variant_names)]
+#[allow(dead_code, clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum IdsParseError {
     #[error("some ids are invalid")]
@@ -63,6 +68,7 @@ pub enum IdsParseError {
     FileContentInvalid,
 }
 
+#[allow(dead_code)]
 fn parse_id_file(src: &str) -> Result<Vec<String>, IdsParseError> {
     let data = std::fs::read_to_string(src).map_err(|_| IdsParseError::FileNotFound)?;
     Ok(lazy_regex!(r"^([a-f0-9]{24})\r?$"m)
@@ -71,57 +77,232 @@ fn parse_id_file(src: &str) -> Result<Vec<String>, IdsParseError> {
         .collect::<Vec<_>>())
 }
 
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Only download the listed emotes
+    Download,
+    /// Probe downloaded emotes and check them against the sticker rules
+    Inspect,
+    /// Extract and resize the frames of downloaded emotes
+    Extract,
+    /// Run the whole pipeline, reusing the results of previous runs (default)
+    Convert,
+    /// Bundle converted stickers into WhatsApp sticker packs
+    Pack {
+        /// Name of the pack
+        #[structopt(long)]
+        name: String,
+
+        /// Publisher of the pack
+        #[structopt(long)]
+        publisher: String,
+
+        /// Emoji assigned to every sticker
+        #[structopt(long, default_value = "🙂")]
+        emoji: String,
+
+        /// Where to write the pack
+        #[structopt(short, long, default_value = "./pack/")]
+        #[structopt(parse(try_from_str = parse_dir_path))]
+        output: PathBuf,
+    },
+    /// Check converted stickers against the sticker rules
+    Validate,
+    /// Add emote files of a folder or `.zip` archive to the download directory
+    ///
+    /// Files are named `<id>.<ext>`, the source is taken from a `<source>/` parent folder,
+    /// `--source` or the `.http.json` sidecar next to the file.
+    Import {
+        /// Folder or `.zip` archive, e.g. someone else's download directory
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Source of files that aren't in a `<source>/` folder
+        #[structopt(long)]
+        source: Option<String>,
+    },
+    /// Remove extracted and resized frames
+    Clean {
+        /// Also remove downloads and converted stickers
+        #[structopt(long)]
+        all: bool,
+    },
+    /// Check the binaries, directories and sources and suggest fixes
+    Doctor,
+    /// Generate a self-contained HTML page previewing the converted stickers
+    Gallery {
+        /// JSON report of a previous run to show details from
+        #[structopt(long, parse(from_os_str))]
+        report: Option<PathBuf>,
+
+        /// Where to write the HTML page
+        #[structopt(short, long, default_value = "./gallery.html")]
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+}
+
+impl Command {
+    /// Optional binaries the command can't do without
+    pub fn needed_binaries(&self, settings: &Settings) -> Vec<&'static str> {
+        let downloads = matches!(self, Command::Download | Command::Convert);
+        let transcodes = settings.download.formats.iter().any(|&f| f != Format::Webp);
+        let encodes = matches!(self, Command::Convert | Command::Pack { .. });
+        // imported GIF, PNG and AVIF files are transcoded whatever the formats are set to
+        let imports = matches!(self, Command::Import { .. });
+        if encodes || imports || (downloads && transcodes) {
+            vec![binaries::MAGICK.name]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "convertoid", about = "Convert stuff to WhatsApp stickers.")]
 pub struct Opt {
     /// IDs of emotes from 7TV to use
     #[structopt(long = "7tv")]
-    #[structopt(parse(try_from_str = SevenTvId::parse_id))]
-    pub seven_tv_ids: Vec<SevenTvId>,
+    pub seven_tv_ids: Vec<String>,
 
     /// IDs of emotes from BTTV to use
     #[structopt(long = "bttv")]
-    #[structopt(parse(try_from_str = BttvId::parse_id))]
-    pub bttv_ids: Vec<BttvId>,
+    pub bttv_ids: Vec<String>,
+
+    /// Emotes of any registered source, as `<source>:<id>`
+    #[structopt(long = "emote")]
+    pub emotes: Vec<String>,
+
+    /// Register the `fixture` source serving `<id>.webp` files from this directory
+    #[structopt(long, parse(from_os_str))]
+    pub fixtures: Option<PathBuf>,
 
     /// Names of SVGs to use
+    #[allow(dead_code)]
     #[structopt(long = "svg")]
     pub svg_names: Vec<String>,
 
-    /// Where to save downloaded emotes
-    #[structopt(long = "dl-dir", default_value = "./dl/")]
-    #[structopt(parse(try_from_str = parse_dir_path))]
-    pub download_dir: PathBuf,
+    /// Where to save downloaded emotes, overrides `dirs.download` of the config
+    #[structopt(long = "dl-dir", parse(from_os_str))]
+    pub download_dir: Option<PathBuf>,
 
-    /// Where to save extracted frames
-    #[structopt(long = "raw-frames-dir", default_value = "./frames-raw/")]
-    #[structopt(parse(try_from_str = parse_dir_path))]
-    pub raw_frames_dir: PathBuf,
+    /// Where to save extracted frames, overrides `dirs.raw_frames` of the config
+    #[structopt(long = "raw-frames-dir", parse(from_os_str))]
+    pub raw_frames_dir: Option<PathBuf>,
 
-    /// Where to save the resized frames
-    #[structopt(long = "resized-frames-dir", default_value = "./frames-resized/")]
-    #[structopt(parse(try_from_str = parse_dir_path))]
-    pub resized_frames_dir: PathBuf,
+    /// Where to save the resized frames, overrides `dirs.resized_frames` of the config
+    #[structopt(long = "resized-frames-dir", parse(from_os_str))]
+    pub resized_frames_dir: Option<PathBuf>,
 
-    /// Where to put converted static stickers
-    #[structopt(long = "out-static-dir", default_value = "./out-static/")]
-    #[structopt(parse(try_from_str = parse_dir_path))]
-    pub out_static_dir: PathBuf,
+    /// Where to put converted static stickers, overrides `dirs.out_static` of the config
+    #[structopt(long = "out-static-dir", parse(from_os_str))]
+    pub out_static_dir: Option<PathBuf>,
 
-    /// Where to put converted animated stickers
-    #[structopt(long = "out-anim-dir", default_value = "./out-anim/")]
-    #[structopt(parse(try_from_str = parse_dir_path))]
-    pub out_anim_dir: PathBuf,
+    /// Where to put converted animated stickers, overrides `dirs.out_anim` of the config
+    #[structopt(long = "out-anim-dir", parse(from_os_str))]
+    pub out_anim_dir: Option<PathBuf>,
+
+    /// How frames are resampled: `auto`, `nearest`, `lanczos` or `bicubic`,
+    /// overrides `scaling.scaler` of the config
+    #[structopt(long)]
+    pub scaler: Option<Scaler>,
+
+    /// Turn static emotes into animated stickers: `shake`, `spin`, `bounce`, `pulse`,
+    /// `rainbow` or `zoom`, overrides `animation.motion` of the config
+    #[structopt(long)]
+    pub animate: Option<Motion>,
+
+    /// Draw this caption on every sticker, needs `caption.font` of the config
+    #[structopt(long)]
+    pub caption: Option<String>,
+
+    /// Named profile of the configuration to use, e.g. `whatsapp` or `hq`
+    #[structopt(long)]
+    pub profile: Option<String>,
+
+    /// Configuration file to read after `convertoid.toml`
+    #[structopt(long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Write a JSON report with one record per emote to this file
+    #[structopt(long, parse(from_os_str))]
+    pub report: Option<PathBuf>,
 
     /// Force processing of emotes that are unlikely to fit
+    #[allow(dead_code)]
     #[structopt(long)]
     pub force: bool,
 
-    /// Only parse arguments, don't process anything
+    /// Stop at the first emote that fails instead of processing the rest
     #[structopt(long)]
-    pub dry_run: bool,
+    pub fail_fast: bool,
+
+    /// Revalidate existing downloads with conditional requests, frames and stickers
+    /// of emotes that changed are made again
+    #[structopt(long, conflicts_with = "offline")]
+    pub refresh: bool,
 
-    /// Only downloads the listed emotes, don't convert
+    /// Never touch the network, emotes that aren't downloaded yet fail
     #[structopt(long)]
-    pub download: bool,
+    pub offline: bool,
+
+    /// Print what would be done for every emote without processing anything
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+impl Opt {
+    /// Every source the emotes can be taken from, including the fixtures
+    pub fn sources(&self) -> Sources {
+        let mut sources = Sources::default();
+        if let Some(dir) = &self.fixtures {
+            sources.register(Fixture::new(dir));
+        }
+        sources
+    }
+
+    pub fn to_emote_ids(&self, sources: &Sources) -> Result<Vec<EmoteId>> {
+        let mut ids = Vec::with_capacity(self.bttv_ids.len() + self.seven_tv_ids.len());
+        for id in &self.bttv_ids {
+            ids.push(sources.parse("bttv", id)?);
+        }
+        for id in &self.seven_tv_ids {
+            ids.push(sources.parse("7tv", id)?);
+        }
+        for id in &self.emotes {
+            ids.push(sources.parse_qualified(id)?);
+        }
+        Ok(ids)
+    }
+
+    /// Overrides the directories, the scaler, the motion and the caption of the configuration
+    /// with the given flags
+    pub fn apply_to(&self, settings: &mut Settings) {
+        let dirs = &mut settings.dirs;
+        let overrides = [
+            (&mut dirs.download, &self.download_dir),
+            (&mut dirs.raw_frames, &self.raw_frames_dir),
+            (&mut dirs.resized_frames, &self.resized_frames_dir),
+            (&mut dirs.out_static, &self.out_static_dir),
+            (&mut dirs.out_anim, &self.out_anim_dir),
+        ];
+        for (dir, flag) in overrides {
+            if let Some(path) = flag {
+                *dir = path.clone();
+            }
+        }
+        if let Some(scaler) = self.scaler {
+            settings.scaling.scaler = scaler;
+        }
+        if let Some(motion) = self.animate {
+            settings.animation.motion = Some(motion);
+        }
+        if let Some(text) = &self.caption {
+            settings.caption.enabled = true;
+            settings.caption.text = Some(text.clone());
+        }
+    }
 }
@@ -0,0 +1,410 @@
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde_json::json;
+
+use crate::batch::{self, BatchElement, Outcome, Summary};
+use crate::context::Context;
+use crate::emote::{Emote, Sticker};
+use crate::emote_ext::EmoteId;
+use crate::error::{Error, Stage, ValidationError};
+
+/// Limits of the WhatsApp sticker pack format
+pub const PACK_MIN_STICKERS: usize = 3;
+pub const PACK_MAX_STICKERS: usize = 30;
+
+pub type StickerResult = Result<Sticker, Error>;
+
+/// Downloads, probes, extracts, resizes and encodes a single emote
+pub async fn convert_one(ctx: &Context, id: &EmoteId) -> StickerResult {
+    Emote::new(ctx, id).await?.to_sticker(ctx).await
+}
+
+/// Only downloads the emotes
+pub async fn download(ctx: &Context, ids: &[EmoteId]) -> Summary {
+    ctx.progress.start(ids.len(), &[Stage::Download]);
+    let elements = batch::run(
+        ids,
+        ctx.settings.concurrency.process,
+        ctx.fail_fast,
+        |id| async move {
+            let result = match Emote::download(ctx, id).await {
+                Ok(()) => crate::fs::file_size(ctx.download_path(id)).await,
+                Err(err) => Err(err),
+            };
+            BatchElement {
+                id: id.clone(),
+                result: result.map_err(|source| Error::Download {
+                    id: id.clone(),
+                    source,
+                }),
+            }
+        },
+    )
+    .await;
+
+    let mut summary = Summary::default();
+    summary.extend(elements, |id, size| Outcome::Succeeded {
+        path: ctx.download_path(&id),
+        size: Some(size),
+    });
+    summary
+}
+
+/// Probes downloaded emotes and checks them against the sticker rules
+pub async fn inspect(ctx: &Context, ids: &[EmoteId]) -> Summary {
+    ctx.progress.start(ids.len(), &[Stage::Probe]);
+    let elements = batch::run(
+        ids,
+        ctx.settings.concurrency.process,
+        ctx.fail_fast,
+        |id| async move {
+            let result = match Emote::webp_info(ctx, id).await {
+                Ok(info) => {
+                    ctx.progress.println(format!("{id}: {info}"));
+                    Emote::validate_durations(ctx, id, &info)
+                }
+                Err(source) => Err(Error::Probe {
+                    id: id.clone(),
+                    source,
+                }),
+            };
+            BatchElement {
+                id: id.clone(),
+                result,
+            }
+        },
+    )
+    .await;
+
+    let mut summary = Summary::default();
+    summary.extend(elements, |id, ()| Outcome::Succeeded {
+        path: ctx.download_path(&id),
+        size: None,
+    });
+    summary
+}
+
+/// Extracts and resizes the frames of downloaded emotes
+pub async fn extract(ctx: &Context, ids: &[EmoteId]) -> Summary {
+    ctx.progress
+        .start(ids.len(), &[Stage::Extract, Stage::Resize]);
+    let elements = batch::run(
+        ids,
+        ctx.settings.concurrency.process,
+        ctx.fail_fast,
+        |id| async move {
+            let extract_err = |stage| {
+                move |source| Error::Extraction {
+                    id: id.clone(),
+                    stage,
+                    source,
+                }
+            };
+            let resize = || async {
+                let info = ctx.bin.webp_info.info(ctx.download_path(id)).await?;
+                Emote::resize_frames(ctx, id, &info).await
+            };
+            let result = match Emote::extract_frames(ctx, id).await {
+                Ok(_) => resize().await.map_err(extract_err(Stage::Resize)),
+                Err(err) => Err(extract_err(Stage::Extract)(err)),
+            };
+            BatchElement {
+                id: id.clone(),
+                result,
+            }
+        },
+    )
+    .await;
+
+    let mut summary = Summary::default();
+    summary.extend(elements, |_, frames| Outcome::Succeeded {
+        path: frames.dir,
+        size: None,
+    });
+    summary
+}
+
+/// Runs the whole pipeline, reusing the results of previous runs
+pub async fn convert(ctx: &Context, ids: &[EmoteId]) -> Summary {
+    ctx.progress.start(
+        ids.len(),
+        &[
+            Stage::Download,
+            Stage::Probe,
+            Stage::Extract,
+            Stage::Resize,
+            Stage::Encode,
+        ],
+    );
+
+    let mut summary = Summary::default();
+    let mut processed = Vec::with_capacity(ids.len());
+    for element in Emote::new_batch(ctx, ids, ctx.settings.concurrency.process).await {
+        match element.result {
+            Ok(emote) => processed.push(emote),
+            Err(err) => {
+                warn!("couldn't process `{:?}`: {}", element.id, err);
+                summary.push_error(element.id, err);
+            }
+        }
+    }
+
+    if !(ctx.fail_fast && summary.has_failures()) {
+        let stickers =
+            Emote::to_sticker_batch(ctx, &processed, ctx.settings.concurrency.encode).await;
+        summary.extend(stickers, |_, sticker| sticker.into_outcome());
+    }
+    summary
+}
+
+/// The converted sticker of an emote, animated ones take precedence
+pub async fn sticker_path(ctx: &Context, id: &EmoteId) -> Option<PathBuf> {
+    for path in [ctx.anim_out_path(id), ctx.static_out_path(id)] {
+        if tokio::fs::metadata(&path).await.is_ok() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+async fn validate_sticker(ctx: &Context, id: &EmoteId) -> Result<(PathBuf, u64), Error> {
+    let not_converted = |source| Error::NotConverted {
+        id: id.clone(),
+        source,
+    };
+    let path = sticker_path(ctx, id)
+        .await
+        .ok_or_else(|| not_converted(anyhow!("no converted sticker found")))?;
+    let info = ctx.bin.webp_info.info(&path).await.map_err(not_converted)?;
+    let size = crate::fs::file_size(&path).await.map_err(not_converted)?;
+
+    let limits = &ctx.settings.limits;
+    let limit = if info.is_animated() {
+        limits.animated_size
+    } else {
+        limits.static_size
+    };
+    if size > limit {
+        return Err(Error::SizeLimit {
+            id: id.clone(),
+            size,
+            limit,
+        });
+    }
+    if info.size != ctx.settings.sticker_size() {
+        let source = ValidationError::Dimensions(info.width(), info.height());
+        return Err(Error::Validation {
+            id: id.clone(),
+            source,
+        });
+    }
+    Emote::validate_durations(ctx, id, &info)?;
+
+    Ok((path, size))
+}
+
+/// Checks converted stickers against the sticker rules
+pub async fn validate(ctx: &Context, ids: &[EmoteId]) -> Summary {
+    ctx.progress.start(ids.len(), &[Stage::Validate]);
+    let elements = batch::run(
+        ids,
+        ctx.settings.concurrency.process,
+        ctx.fail_fast,
+        |id| async move {
+            let start = Instant::now();
+            let result = validate_sticker(ctx, id).await;
+            ctx.finish_stage(id, Stage::Validate, true, start.elapsed());
+            BatchElement {
+                id: id.clone(),
+                result,
+            }
+        },
+    )
+    .await;
+
+    let mut summary = Summary::default();
+    summary.extend(elements, |_, (path, size)| Outcome::Succeeded {
+        path,
+        size: Some(size),
+    });
+    summary
+}
+
+/// How many of `len` stickers of one kind go in each pack, `None` if they're too few for one.
+///
+/// They're spread evenly so the last pack doesn't end up with too few stickers.
+pub fn pack_chunk_size(len: usize) -> Option<usize> {
+    if len < PACK_MIN_STICKERS {
+        return None;
+    }
+    let count = len.div_ceil(PACK_MAX_STICKERS);
+    Some(len.div_ceil(count))
+}
+
+/// Copies the stickers into `dir` next to a tray icon and describes them
+async fn write_pack(
+    ctx: &Context,
+    dir: &Path,
+    stickers: &[(EmoteId, PathBuf)],
+    emoji: &str,
+) -> Result<Vec<serde_json::Value>> {
+    crate::fs::assert_dir(dir).await?;
+    ctx.bin
+        .magick()?
+        .tray_icon(&stickers[0].1, dir.join("tray.png"))
+        .await?;
+
+    let mut entries = Vec::with_capacity(stickers.len());
+    for (_, path) in stickers {
+        let file_name = path.file_name().unwrap();
+        tokio::fs::copy(path, dir.join(file_name)).await?;
+        entries.push(json!({
+            "image_file": file_name.to_string_lossy(),
+            "emojis": [emoji],
+        }));
+    }
+    Ok(entries)
+}
+
+/// Bundles converted stickers into WhatsApp sticker packs in `output`
+pub async fn pack(
+    ctx: &Context,
+    ids: &[EmoteId],
+    name: &str,
+    publisher: &str,
+    emoji: &str,
+    output: &Path,
+) -> Result<Summary> {
+    let mut summary = Summary::default();
+    let (mut animated, mut still) = (Vec::new(), Vec::new());
+    for id in ids {
+        match sticker_path(ctx, id).await {
+            Some(path) if path.starts_with(&ctx.settings.dirs.out_anim) => {
+                animated.push((id.clone(), path))
+            }
+            Some(path) => still.push((id.clone(), path)),
+            None => {
+                let source = anyhow!("no converted sticker found");
+                let id = id.clone();
+                summary.push_error(id.clone(), Error::NotConverted { id, source });
+            }
+        }
+    }
+
+    let mut packs = Vec::new();
+    for (kind, stickers) in [("static", still), ("animated", animated)] {
+        if stickers.is_empty() {
+            continue;
+        }
+        let Some(chunk_size) = pack_chunk_size(stickers.len()) else {
+            warn!(
+                "skipping the {} {kind} stickers, WhatsApp rejects packs with less than \
+                {PACK_MIN_STICKERS}",
+                stickers.len()
+            );
+            for (id, _) in &stickers {
+                let source = ValidationError::PackTooSmall(stickers.len(), PACK_MIN_STICKERS);
+                let id = id.clone();
+                summary.push_error(id.clone(), Error::Validation { id, source });
+            }
+            continue;
+        };
+        for (index, chunk) in stickers.chunks(chunk_size).enumerate() {
+            let identifier = format!("{kind}_{}", index + 1);
+            let dir = output.join(&identifier);
+            let entries = write_pack(ctx, &dir, chunk, emoji).await?;
+            packs.push(json!({
+                "identifier": identifier,
+                "name": format!("{name} ({kind} {})", index + 1),
+                "publisher": publisher,
+                "tray_image_file": "tray.png",
+                "image_data_version": "1",
+                "avoid_cache": false,
+                "publisher_email": "",
+                "publisher_website": "",
+                "privacy_policy_website": "",
+                "license_agreement_website": "",
+                "animated_sticker_pack": kind == "animated",
+                "stickers": entries,
+            }));
+            for (id, path) in chunk {
+                let path = dir.join(path.file_name().unwrap());
+                let size = crate::fs::file_size(&path).await.ok();
+                summary.push(id.clone(), Outcome::Succeeded { path, size });
+            }
+            info!("wrote pack `{identifier}` with {} stickers", chunk.len());
+        }
+    }
+
+    let contents = json!({
+        "android_play_store_link": "",
+        "ios_app_store_link": "",
+        "sticker_packs": packs,
+    });
+    let contents = serde_json::to_vec_pretty(&contents)?;
+    tokio::fs::write(output.join("contents.json"), contents).await?;
+
+    Ok(summary)
+}
+
+/// The directories [`clean`] empties when no emotes are given
+pub fn clean_dirs(ctx: &Context, all: bool) -> Vec<&Path> {
+    let config = &ctx.settings.dirs;
+    let mut dirs = vec![config.raw_frames.as_path(), config.resized_frames.as_path()];
+    if all {
+        dirs.extend([
+            config.download.as_path(),
+            config.out_static.as_path(),
+            config.out_anim.as_path(),
+        ]);
+    }
+    dirs
+}
+
+/// The files and directories [`clean`] removes for an emote
+pub fn clean_paths(ctx: &Context, id: &EmoteId, all: bool) -> Vec<PathBuf> {
+    let mut paths = ctx.frame_paths(id);
+    if all {
+        let dl_path = ctx.download_path(id);
+        paths.extend([
+            crate::cache::entry_path(&dl_path),
+            dl_path,
+            ctx.static_out_path(id),
+            ctx.anim_out_path(id),
+        ]);
+    }
+    paths
+}
+
+/// Removes extracted and resized frames, `all` also removes downloads and stickers
+pub async fn clean(ctx: &Context, ids: &[EmoteId], all: bool) -> Result<Summary> {
+    let mut summary = Summary::default();
+
+    if ids.is_empty() {
+        for dir in clean_dirs(ctx, all) {
+            for entry in crate::list_dir::entries(dir).await {
+                crate::fs::remove(entry.path()).await?;
+            }
+            info!("cleaned `{dir:?}`");
+        }
+        return Ok(summary);
+    }
+
+    for id in ids {
+        for path in &clean_paths(ctx, id, all) {
+            crate::fs::remove(path).await?;
+        }
+        info!("cleaned emote `{id:?}`");
+        summary.push(
+            id.clone(),
+            Outcome::Succeeded {
+                path: ctx.raw_frames_path(id),
+                size: None,
+            },
+        );
+    }
+    Ok(summary)
+}
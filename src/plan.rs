@@ -0,0 +1,325 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use convertoid::cache;
+use convertoid::context::Context;
+use convertoid::emote::Emote;
+use convertoid::emote_ext::EmoteId;
+use convertoid::webp::WebpInfo;
+use convertoid::{import, pipeline};
+
+use crate::opt::Command;
+
+/// What a single stage would do for one emote
+struct Step {
+    stage: &'static str,
+    run: bool,
+    detail: String,
+}
+
+impl Step {
+    fn new(stage: &'static str, run: bool, detail: String) -> Step {
+        Step { stage, run, detail }
+    }
+}
+
+/// Work that would be done by the whole run
+#[derive(Debug, Default)]
+struct Estimate {
+    downloads: usize,
+    extractions: usize,
+    resizes: usize,
+    frames: usize,
+    encodes: usize,
+    unknown: usize,
+}
+
+async fn exists(path: &Path) -> bool {
+    tokio::fs::metadata(path).await.is_ok()
+}
+
+async fn has_files(path: &Path) -> bool {
+    exists(path).await && !convertoid::fs::is_dir_empty(path).await.unwrap_or(true)
+}
+
+async fn plan_emote(ctx: &Context, cmd: &Command, id: &EmoteId, est: &mut Estimate) -> Vec<Step> {
+    let pipeline = matches!(cmd, Command::Convert);
+    let mut steps = Vec::new();
+
+    let dl_path = ctx.download_path(id);
+    let downloaded = exists(&dl_path).await;
+    if pipeline || matches!(cmd, Command::Download) {
+        let detail = if downloaded {
+            format!("cached at {dl_path:?}")
+        } else if ctx.offline {
+            format!("MISSING, {dl_path:?} can't be fetched offline")
+        } else {
+            est.downloads += 1;
+            match ctx.renditions(id) {
+                Ok((source, renditions)) => {
+                    let first = renditions.first().map(|&r| source.url(id.as_str(), r));
+                    format!(
+                        "{} -> {dl_path:?}, {} fallbacks",
+                        first.unwrap_or_default(),
+                        renditions.len().saturating_sub(1)
+                    )
+                }
+                Err(err) => err.to_string(),
+            }
+        };
+        steps.push(Step::new("download", !downloaded, detail));
+    }
+
+    let info = if downloaded {
+        ctx.bin.webp_info.info(&dl_path).await.ok()
+    } else {
+        None
+    };
+    if pipeline || matches!(cmd, Command::Inspect) {
+        let detail = match &info {
+            Some(info) => info.to_string(),
+            None => "after download".to_string(),
+        };
+        steps.push(Step::new("probe", true, detail));
+    }
+
+    if pipeline || matches!(cmd, Command::Extract) {
+        let raw = ctx.raw_frames_path(id);
+        let extracted = has_files(&raw).await;
+        if !extracted {
+            est.extractions += 1;
+        }
+        steps.push(Step::new("extract", !extracted, format!("{raw:?}")));
+
+        let resized_path = ctx.resized_frames_path(id);
+        let resized = has_files(&resized_path).await;
+        if !resized {
+            est.resizes += 1;
+            est.frames += info.as_ref().map_or(1, |info| info.frame_count().max(1));
+        }
+        steps.push(Step::new("resize", !resized, format!("{resized_path:?}")));
+    }
+
+    let animation = ctx.settings.animation.for_emote(&format!("{id:?}"));
+    let motion = match &info {
+        Some(info) => Emote::motion(ctx, id, info),
+        None => animation.motion,
+    };
+    if let (Some(motion), true) = (motion, pipeline || matches!(cmd, Command::Extract)) {
+        let path = ctx.motion_frames_path(id);
+        let animated = has_files(&path).await;
+        if !animated {
+            est.frames += animation.motion_frames;
+        }
+        let condition = if info.is_none() { " if static" } else { "" };
+        let detail = format!(
+            "{motion:?} with {} frames -> {path:?}{condition}",
+            animation.motion_frames
+        );
+        steps.push(Step::new("animate", !animated, detail));
+    }
+
+    if pipeline {
+        steps.push(plan_encode(ctx, id, info.as_ref(), motion.is_some(), est));
+    }
+
+    steps
+}
+
+/// `moves` tells whether a motion preset is set, it makes static emotes animated
+fn plan_encode(
+    ctx: &Context,
+    id: &EmoteId,
+    info: Option<&WebpInfo>,
+    moves: bool,
+    est: &mut Estimate,
+) -> Step {
+    let presets = &ctx.settings.encoder.presets;
+    let names = presets
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let detail = match info {
+        Some(info) if info.is_animated() || moves => {
+            est.encodes += presets.len();
+            format!("img2webp with up to {names} -> {:?}", ctx.anim_out_path(id))
+        }
+        Some(_) => {
+            est.encodes += 1;
+            format!("magick -> {:?}", ctx.static_out_path(id))
+        }
+        None if moves => {
+            est.unknown += 1;
+            est.encodes += presets.len();
+            format!("img2webp with up to {names} -> {:?}", ctx.anim_out_path(id))
+        }
+        None => {
+            est.unknown += 1;
+            est.encodes += presets.len();
+            format!(
+                "img2webp with up to {names} -> {:?} if animated, \
+                magick -> {:?} otherwise",
+                ctx.anim_out_path(id),
+                ctx.static_out_path(id)
+            )
+        }
+    };
+    Step::new("encode", true, detail)
+}
+
+async fn plan_clean(ctx: &Context, ids: &[EmoteId], all: bool) {
+    if ids.is_empty() {
+        for dir in pipeline::clean_dirs(ctx, all) {
+            println!("would empty {dir:?}");
+        }
+        return;
+    }
+    for id in ids {
+        for path in pipeline::clean_paths(ctx, id, all) {
+            if exists(&path).await {
+                println!("would remove {path:?}");
+            }
+        }
+    }
+}
+
+async fn plan_validate(ctx: &Context, ids: &[EmoteId]) {
+    for id in ids {
+        match pipeline::sticker_path(ctx, id).await {
+            Some(path) => println!("would check {path:?}"),
+            None => println!("{} {id}: MISSING, not converted yet", id.source()),
+        }
+    }
+    println!("validate doesn't write anything");
+}
+
+async fn plan_pack(ctx: &Context, ids: &[EmoteId], output: &Path) {
+    let (mut animated, mut still) = (Vec::new(), Vec::new());
+    for id in ids {
+        match pipeline::sticker_path(ctx, id).await {
+            Some(path) if path.starts_with(&ctx.settings.dirs.out_anim) => animated.push(path),
+            Some(path) => still.push(path),
+            None => println!("{} {id}: MISSING, not converted yet", id.source()),
+        }
+    }
+    for (kind, stickers) in [("static", still), ("animated", animated)] {
+        if stickers.is_empty() {
+            continue;
+        }
+        let Some(chunk_size) = pipeline::pack_chunk_size(stickers.len()) else {
+            println!(
+                "would skip the {} {kind} stickers, a pack needs at least {}",
+                stickers.len(),
+                pipeline::PACK_MIN_STICKERS
+            );
+            continue;
+        };
+        for (index, chunk) in stickers.chunks(chunk_size).enumerate() {
+            let dir = output.join(format!("{kind}_{}", index + 1));
+            println!(
+                "would write {dir:?} with {} stickers and `tray.png`",
+                chunk.len()
+            );
+        }
+    }
+    println!("would write {:?}", output.join("contents.json"));
+}
+
+async fn plan_import(ctx: &Context, path: &Path, source: Option<&str>) -> Result<()> {
+    let files = import::plan(ctx, path, source).await?;
+    for (file, id) in &files {
+        let dl_path = ctx.download_path(id);
+        let action = if exists(&dl_path).await {
+            "would replace"
+        } else {
+            "would write"
+        };
+        println!(
+            "{} {id}: {action} {dl_path:?} and {:?} with {file:?}",
+            id.source(),
+            cache::entry_path(&dl_path)
+        );
+    }
+    println!(
+        "{} files, emotes whose download changes would lose their frames and stickers",
+        files.len()
+    );
+    Ok(())
+}
+
+/// Prints what `cmd` would do without touching the network or the output directories
+pub async fn print(ctx: &Context, cmd: &Command, ids: &[EmoteId]) -> Result<()> {
+    println!("binaries:");
+    let needed = cmd.needed_binaries(&ctx.settings);
+    let mut missing = 0;
+    for (tool, result) in ctx.bin.check_each(3).await {
+        let name = tool.name;
+        match result {
+            Ok(info) => match info.version {
+                Some(version) => println!("  {name:<10} found {version}"),
+                None => println!("  {name:<10} found"),
+            },
+            Err(err) if tool.optional && !needed.contains(&name) => {
+                println!("  {name:<10} not needed: {err}")
+            }
+            Err(err) => {
+                missing += 1;
+                println!("  {name:<10} MISSING: {err}");
+            }
+        }
+    }
+
+    match cmd {
+        Command::Clean { all } => {
+            plan_clean(ctx, ids, *all).await;
+            return Ok(());
+        }
+        Command::Validate => {
+            plan_validate(ctx, ids).await;
+            return Ok(());
+        }
+        Command::Pack { output, .. } => {
+            plan_pack(ctx, ids, output).await;
+            return Ok(());
+        }
+        Command::Import { path, source } => {
+            return plan_import(ctx, path, source.as_deref()).await;
+        }
+        Command::Download | Command::Inspect | Command::Extract | Command::Convert => {}
+        Command::Doctor | Command::Gallery { .. } => {
+            unreachable!("the doctor and the gallery run without a context")
+        }
+    }
+
+    let mut est = Estimate::default();
+    for id in ids {
+        println!("{} {id}:", id.source());
+        for step in plan_emote(ctx, cmd, id, &mut est).await {
+            let action = if step.run { "run" } else { "cached" };
+            println!("  {:<9} {:<7} {}", step.stage, action, step.detail);
+        }
+    }
+
+    println!(
+        "{} emotes: {} downloads, {} extractions, {} resizes ({} frames), \
+        up to {} encoder runs",
+        ids.len(),
+        est.downloads,
+        est.extractions,
+        est.resizes,
+        est.frames,
+        est.encodes
+    );
+    if est.unknown > 0 {
+        println!(
+            "{} emotes aren't downloaded yet, their frame counts are unknown",
+            est.unknown
+        );
+    }
+    if missing > 0 {
+        println!("{missing} binaries are missing, the run would fail");
+    }
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::io::IsTerminal;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::emote_ext::EmoteId;
+use crate::error::Stage;
+
+/// Progress bars for every stage and a spinner for every emote in flight.
+///
+/// Draws nothing if stdout is not a terminal.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    multi: MultiProgress,
+    bars: Arc<Mutex<Vec<(Stage, ProgressBar)>>>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        let multi = MultiProgress::new();
+        if !std::io::stdout().is_terminal() {
+            multi.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        Progress {
+            multi,
+            bars: Default::default(),
+        }
+    }
+    pub fn multi(&self) -> &MultiProgress {
+        &self.multi
+    }
+
+    /// Adds a bar of length `len` for every stage, in the given order
+    pub fn start(&self, len: usize, stages: &[Stage]) {
+        let style = ProgressStyle::with_template("{prefix:>9} [{bar:40}] {pos}/{len}")
+            .unwrap()
+            .progress_chars("=> ");
+        let mut bars = self.bars.lock().unwrap();
+        for &stage in stages {
+            let bar = self.multi.add(ProgressBar::new(len as u64));
+            bar.set_style(style.clone());
+            bar.set_prefix(stage.to_string());
+            bars.push((stage, bar));
+        }
+    }
+    pub fn inc(&self, stage: Stage) {
+        let bars = self.bars.lock().unwrap();
+        if let Some((_, bar)) = bars.iter().find(|(s, _)| *s == stage) {
+            bar.inc(1);
+        }
+    }
+    /// Prints a line above the bars, or just prints it if they're hidden
+    pub fn println(&self, line: impl AsRef<str>) {
+        self.multi.suspend(|| println!("{}", line.as_ref()));
+    }
+    /// Removes every bar, e.g. before printing the summary
+    pub fn finish(&self) {
+        for (_, bar) in self.bars.lock().unwrap().drain(..) {
+            bar.finish_and_clear();
+            self.multi.remove(&bar);
+        }
+    }
+
+    pub fn spinner(&self, id: &EmoteId) -> Spinner {
+        let bar = self.multi.add(ProgressBar::new_spinner());
+        bar.set_style(ProgressStyle::with_template("{spinner} {prefix} {msg}").unwrap());
+        bar.set_prefix(id.to_string());
+        bar.enable_steady_tick(Duration::from_millis(100));
+        Spinner {
+            multi: self.multi.clone(),
+            bar,
+        }
+    }
+}
+
+/// Spinner for a single emote, removed once dropped
+pub struct Spinner {
+    multi: MultiProgress,
+    bar: ProgressBar,
+}
+
+impl Spinner {
+    pub fn set_message(&self, msg: impl Into<String>) {
+        self.bar.set_message(msg.into());
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+        self.multi.remove(&self.bar);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::alpha::CleanupRecord;
+use crate::batch::{Outcome, Summary};
+use crate::emote::Preset;
+use crate::emote_ext::EmoteId;
+use crate::error::{Error, Stage};
+use crate::source::Rendition;
+use crate::webp::WebpInfo;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InfoSummary {
+    pub animated: bool,
+    pub width: i32,
+    pub height: i32,
+    pub frame_count: usize,
+    pub total_duration_ms: i32,
+    pub min_duration_ms: Option<i32>,
+    pub max_duration_ms: Option<i32>,
+}
+
+impl From<&WebpInfo> for InfoSummary {
+    fn from(info: &WebpInfo) -> Self {
+        Self {
+            animated: info.is_animated(),
+            width: info.width(),
+            height: info.height(),
+            frame_count: info.frame_count(),
+            total_duration_ms: info.total_duration(),
+            min_duration_ms: info.min_duration(),
+            max_duration_ms: info.max_duration(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageRecord {
+    pub stage: Stage,
+    /// `false` if the result of a previous run was reused
+    pub executed: bool,
+    pub millis: u128,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationRecord {
+    pub rule: String,
+    pub value: i64,
+    pub limit: i64,
+    pub passed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    pub kind: String,
+    pub stage: Option<Stage>,
+    pub message: String,
+    pub stderr: Option<String>,
+    pub exit_code: i32,
+}
+
+impl From<&Error> for ErrorRecord {
+    fn from(err: &Error) -> Self {
+        Self {
+            kind: err.kind().to_string(),
+            stage: err.stage(),
+            message: err.reason(),
+            stderr: err.stderr().map(str::to_string),
+            exit_code: err.exit_code(),
+        }
+    }
+}
+
+/// Everything that is known about one emote after a run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EmoteRecord {
+    pub source: String,
+    pub id: String,
+    pub name: Option<String>,
+    /// Which file of the source was downloaded, e.g. `4x.webp`
+    pub rendition: Option<String>,
+    pub status: String,
+    pub info: Option<InfoSummary>,
+    pub stages: Vec<StageRecord>,
+    pub validation: Vec<ValidationRecord>,
+    pub preset: Option<Preset>,
+    /// Only present if the frames were resized in this run
+    pub alpha: Option<CleanupRecord>,
+    pub output: Option<PathBuf>,
+    pub size: Option<u64>,
+    pub degraded: Option<String>,
+    pub error: Option<ErrorRecord>,
+}
+
+/// Collects the per emote details while the batches are running
+#[derive(Debug, Default)]
+pub struct Recorder(Mutex<HashMap<EmoteId, EmoteRecord>>);
+
+impl Recorder {
+    fn with(&self, id: &EmoteId, f: impl FnOnce(&mut EmoteRecord)) {
+        let mut records = self.0.lock().unwrap();
+        f(records.entry(id.clone()).or_default());
+    }
+    pub fn stage(&self, id: &EmoteId, stage: Stage, executed: bool, elapsed: Duration) {
+        self.with(id, |record| {
+            record.stages.push(StageRecord {
+                stage,
+                executed,
+                millis: elapsed.as_millis(),
+            })
+        });
+    }
+    pub fn info(&self, id: &EmoteId, info: &WebpInfo) {
+        self.with(id, |record| record.info = Some(info.into()));
+    }
+    pub fn validation(
+        &self,
+        id: &EmoteId,
+        rule: &'static str,
+        value: i64,
+        limit: i64,
+        passed: bool,
+    ) {
+        self.with(id, |record| {
+            record.validation.push(ValidationRecord {
+                rule: rule.to_string(),
+                value,
+                limit,
+                passed,
+            })
+        });
+    }
+    pub fn name(&self, id: &EmoteId, name: String) {
+        self.with(id, |record| record.name = Some(name));
+    }
+    pub fn rendition(&self, id: &EmoteId, rendition: Rendition) {
+        self.with(id, |record| record.rendition = Some(rendition.to_string()));
+    }
+    pub fn preset(&self, id: &EmoteId, preset: Preset) {
+        self.with(id, |record| record.preset = Some(preset));
+    }
+    pub fn alpha(&self, id: &EmoteId, alpha: CleanupRecord) {
+        self.with(id, |record| record.alpha = Some(alpha));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    pub exit_code: i32,
+    pub emotes: Vec<EmoteRecord>,
+}
+
+impl Report {
+    pub fn new(started: DateTime<Utc>, summary: &Summary, recorder: &Recorder) -> Report {
+        let mut records = recorder.0.lock().unwrap();
+        let emotes = summary
+            .outcomes
+            .iter()
+            .map(|(id, outcome)| {
+                let mut record = records.remove(id).unwrap_or_default();
+                record.source = id.source().to_string();
+                record.id = id.to_string();
+                record.status = outcome.status().to_string();
+                match outcome {
+                    Outcome::Succeeded { path, size } => {
+                        record.output = Some(path.clone());
+                        record.size = *size;
+                    }
+                    Outcome::Degraded { path, size, reason } => {
+                        record.output = Some(path.clone());
+                        record.size = Some(*size);
+                        record.degraded = Some(reason.clone());
+                    }
+                    Outcome::Skipped { error } | Outcome::Failed { error } => {
+                        record.size = outcome.size();
+                        record.error = Some(error.into());
+                    }
+                }
+                record
+            })
+            .collect();
+
+        Report {
+            started,
+            finished: Utc::now(),
+            exit_code: summary.exit_code(),
+            emotes,
+        }
+    }
+    pub async fn write_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let json = serde_json::to_vec_pretty(self)?;
+        Ok(tokio::fs::write(path.as_ref(), json).await?)
+    }
+    pub async fn read_from(path: impl AsRef<Path>) -> Result<Report> {
+        let json = tokio::fs::read(path.as_ref()).await?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
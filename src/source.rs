@@ -0,0 +1,358 @@
+use std::fmt::{Debug, Display};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::download::{BodyWriter, Client, Fetched, Validators};
+use crate::emote_ext::EmoteId;
+
+/// What a source knows about an emote besides its image
+#[derive(Debug, Clone, Default)]
+pub struct EmoteMeta {
+    pub name: String,
+    pub animated: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Webp,
+    Gif,
+    Avif,
+    Png,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Webp => "webp",
+            Format::Gif => "gif",
+            Format::Avif => "avif",
+            Format::Png => "png",
+        }
+    }
+    /// Recognizes the format from the first bytes of a file
+    pub fn sniff(head: &[u8]) -> Option<Format> {
+        let at =
+            |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+        if at(0, b"RIFF") && at(8, b"WEBP") {
+            Some(Format::Webp)
+        } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+            Some(Format::Gif)
+        } else if at(0, b"\x89PNG\r\n\x1a\n") {
+            Some(Format::Png)
+        } else if at(4, b"ftyp") && (at(8, b"avif") || at(8, b"avis")) {
+            Some(Format::Avif)
+        } else {
+            None
+        }
+    }
+}
+
+/// One of the files a source offers for an emote
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rendition {
+    /// `1` for `1x`, `4` for `4x` etc.
+    pub scale: u8,
+    /// Height in pixels, if the source guarantees one
+    pub height: Option<u32>,
+    pub format: Format,
+}
+
+impl Rendition {
+    pub const fn new(scale: u8, height: Option<u32>, format: Format) -> Rendition {
+        Rendition {
+            scale,
+            height,
+            format,
+        }
+    }
+}
+
+impl Display for Rendition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x.{}", self.scale, self.format.extension())
+    }
+}
+
+/// Orders the renditions in `formats` to try them one after another.
+///
+/// The smallest ones that reach `target` pixels when scaled up by at most `max_upscale`
+/// come first, then the remaining ones from largest to smallest.
+/// Renditions of the same size are ordered like `formats`.
+pub fn negotiate(
+    renditions: &[Rendition],
+    formats: &[Format],
+    target: u32,
+    max_upscale: f64,
+) -> Vec<Rendition> {
+    let preference = |r: &Rendition| formats.iter().position(|&f| f == r.format);
+    let reaches = |r: &Rendition| {
+        r.height
+            .is_some_and(|h| h as f64 * max_upscale >= target as f64)
+    };
+
+    let mut candidates = renditions
+        .iter()
+        .copied()
+        .filter(|r| preference(r).is_some())
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|r| {
+        let height = r.height.map_or(0, i64::from);
+        let (group, height) = if reaches(r) {
+            (0, height)
+        } else {
+            (1, -height)
+        };
+        (group, height, preference(r))
+    });
+    candidates
+}
+
+/// A provider of emotes, e.g. 7TV or BTTV.
+///
+/// Register new ones with [`Sources::register`].
+pub trait EmoteSource: Debug + Send + Sync {
+    /// Short name, used on the command line and in reports
+    fn name(&self) -> &'static str;
+    /// Checks `input` and returns the canonical form of the id
+    fn parse_id(&self, input: &str) -> Result<String>;
+    /// Every rendition the source may offer
+    fn renditions(&self) -> Vec<Rendition>;
+    /// Where a rendition of the emote is downloaded from
+    fn url(&self, id: &str, rendition: Rendition) -> String;
+    /// Looks up the name of the emote
+    fn metadata<'a>(&'a self, client: &'a Client, id: &'a str) -> BoxFuture<'a, Result<EmoteMeta>>;
+
+    /// Name of the downloaded file, prefixed with the source so ids of different sources don't collide
+    fn file_name(&self, id: &str) -> PathBuf {
+        PathBuf::from(format!("{}-{id}.webp", self.name()))
+    }
+    /// Downloads a rendition of the emote from [`EmoteSource::url`] to `path`
+    /// unless it didn't change since the response `validators` are taken from
+    fn fetch<'a>(
+        &'a self,
+        client: &'a Client,
+        id: &'a str,
+        rendition: Rendition,
+        validators: &'a Validators,
+        path: &'a Path,
+    ) -> BoxFuture<'a, Result<Fetched>> {
+        let url = self.url(id, rendition);
+        Box::pin(async move { client.download_image(&url, validators, path).await })
+    }
+}
+
+/// Both 7TV and BTTV use 24 hex digits
+fn parse_hex_id(input: &str) -> Result<String> {
+    let mut id = [0u8; 12];
+    hex::decode_to_slice(input, &mut id)?;
+    Ok(hex::encode(id))
+}
+
+#[derive(Debug)]
+pub struct SevenTv;
+
+impl EmoteSource for SevenTv {
+    fn name(&self) -> &'static str {
+        "7tv"
+    }
+    fn parse_id(&self, input: &str) -> Result<String> {
+        parse_hex_id(input)
+    }
+    fn renditions(&self) -> Vec<Rendition> {
+        let mut renditions = Vec::with_capacity(16);
+        for format in [Format::Webp, Format::Gif, Format::Avif, Format::Png] {
+            for scale in 1..=4 {
+                renditions.push(Rendition::new(scale, Some(32 * scale as u32), format));
+            }
+        }
+        renditions
+    }
+    fn url(&self, id: &str, rendition: Rendition) -> String {
+        format!("https://cdn.7tv.app/emote/{id}/{rendition}")
+    }
+    fn metadata<'a>(&'a self, client: &'a Client, id: &'a str) -> BoxFuture<'a, Result<EmoteMeta>> {
+        #[derive(Deserialize)]
+        struct Response {
+            name: String,
+            animated: Option<bool>,
+        }
+
+        Box::pin(async move {
+            let url = format!("https://7tv.io/v3/emotes/{id}");
+            let resp: Response = client.get_json(&url).await?;
+            Ok(EmoteMeta {
+                name: resp.name,
+                animated: resp.animated,
+            })
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Bttv;
+
+impl EmoteSource for Bttv {
+    fn name(&self) -> &'static str {
+        "bttv"
+    }
+    fn parse_id(&self, input: &str) -> Result<String> {
+        parse_hex_id(input)
+    }
+    fn renditions(&self) -> Vec<Rendition> {
+        let mut renditions = Vec::with_capacity(9);
+        for format in [Format::Webp, Format::Gif, Format::Png] {
+            for scale in 1..=3 {
+                renditions.push(Rendition::new(scale, Some(28 * scale as u32), format));
+            }
+        }
+        renditions
+    }
+    fn url(&self, id: &str, rendition: Rendition) -> String {
+        format!("https://cdn.betterttv.net/emote/{id}/{rendition}")
+    }
+    fn metadata<'a>(&'a self, client: &'a Client, id: &'a str) -> BoxFuture<'a, Result<EmoteMeta>> {
+        #[derive(Deserialize)]
+        struct Response {
+            code: String,
+            animated: Option<bool>,
+        }
+
+        Box::pin(async move {
+            let url = format!("https://api.betterttv.net/3/emotes/{id}");
+            let resp: Response = client.get_json(&url).await?;
+            Ok(EmoteMeta {
+                name: resp.code,
+                animated: resp.animated,
+            })
+        })
+    }
+}
+
+/// Serves `<id>.webp`, `<id>.gif` or `<id>.png` files from a local directory,
+/// e.g. to run the pipeline offline
+#[derive(Debug)]
+pub struct Fixture {
+    dir: PathBuf,
+}
+
+impl Fixture {
+    pub fn new(dir: impl Into<PathBuf>) -> Fixture {
+        Fixture { dir: dir.into() }
+    }
+}
+
+impl EmoteSource for Fixture {
+    fn name(&self) -> &'static str {
+        "fixture"
+    }
+    fn parse_id(&self, input: &str) -> Result<String> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if input.is_empty() || !input.chars().all(valid) {
+            return Err(anyhow!("only letters, digits, `-` and `_` are allowed"));
+        }
+        Ok(input.to_string())
+    }
+    fn renditions(&self) -> Vec<Rendition> {
+        [Format::Webp, Format::Gif, Format::Png]
+            .map(|format| Rendition::new(1, None, format))
+            .to_vec()
+    }
+    fn url(&self, id: &str, rendition: Rendition) -> String {
+        let file_name = format!("{id}.{}", rendition.format.extension());
+        format!("file://{}", self.dir.join(file_name).display())
+    }
+    fn metadata<'a>(&'a self, _: &'a Client, id: &'a str) -> BoxFuture<'a, Result<EmoteMeta>> {
+        Box::pin(async move {
+            Ok(EmoteMeta {
+                name: id.to_string(),
+                animated: None,
+            })
+        })
+    }
+    /// Always copies the file, changes are detected by comparing the contents
+    fn fetch<'a>(
+        &'a self,
+        client: &'a Client,
+        id: &'a str,
+        rendition: Rendition,
+        _: &'a Validators,
+        path: &'a Path,
+    ) -> BoxFuture<'a, Result<Fetched>> {
+        let url = self.url(id, rendition);
+        let file = self
+            .dir
+            .join(format!("{id}.{}", rendition.format.extension()));
+        Box::pin(async move {
+            let data = tokio::fs::read(file).await?;
+            let mut writer = BodyWriter::create(&url, path, client.max_body_size()).await?;
+            writer.write(&data).await?;
+            Ok(Fetched::Modified {
+                body: writer.finish().await?,
+                validators: Validators::default(),
+            })
+        })
+    }
+}
+
+/// Every source emotes can be taken from, 7TV and BTTV are registered by default
+#[derive(Debug, Clone)]
+pub struct Sources(Vec<Arc<dyn EmoteSource>>);
+
+impl Default for Sources {
+    fn default() -> Self {
+        let mut sources = Self::empty();
+        sources.register(SevenTv);
+        sources.register(Bttv);
+        sources
+    }
+}
+
+impl Sources {
+    pub fn empty() -> Sources {
+        Sources(Vec::new())
+    }
+    /// Adds a source, replacing any registered one with the same name
+    pub fn register(&mut self, source: impl EmoteSource + 'static) {
+        self.0.retain(|existing| existing.name() != source.name());
+        self.0.push(Arc::new(source));
+    }
+    pub fn names(&self) -> Vec<&'static str> {
+        self.0.iter().map(|source| source.name()).collect()
+    }
+    pub fn get(&self, name: &str) -> Result<&dyn EmoteSource> {
+        self.0
+            .iter()
+            .find(|source| source.name() == name)
+            .map(|source| source.as_ref())
+            .ok_or_else(|| {
+                let known = self.names().join(", ");
+                anyhow!("unknown emote source `{name}`, known sources: {known}")
+            })
+    }
+    /// Parses an id of the source called `name`
+    pub fn parse(&self, name: &str, input: &str) -> Result<EmoteId> {
+        let source = self.get(name)?;
+        let id = source
+            .parse_id(input)
+            .map_err(|err| anyhow!("invalid {name} id `{input}`: {err}"))?;
+        Ok(EmoteId::new(source.name(), &id))
+    }
+    /// Parses ids of the form `<source>:<id>`, e.g. `bttv:5f1b0186cf6d2144653d2970`
+    pub fn parse_qualified(&self, input: &str) -> Result<EmoteId> {
+        let (name, id) = input
+            .split_once(':')
+            .ok_or_else(|| anyhow!("`{input}` isn't of the form `<source>:<id>`"))?;
+        self.parse(name, id)
+    }
+    pub fn file_name(&self, id: &EmoteId) -> PathBuf {
+        match self.get(id.source()) {
+            Ok(source) => source.file_name(id.as_str()),
+            Err(_) => PathBuf::from(format!("{}.webp", id.key())),
+        }
+    }
+}
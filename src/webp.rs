@@ -61,6 +61,23 @@ impl WebpInfo {
     }
 }
 
+/// Short description for the user, e.g. `112x112, animated, 24 frames, 1200 ms`
+impl std::fmt::Display for WebpInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (width, height) = self.size;
+        if !self.is_animated() {
+            return write!(f, "{width}x{height}, static");
+        }
+        let count = self.frame_count();
+        let frames = if count == 1 { "frame" } else { "frames" };
+        write!(
+            f,
+            "{width}x{height}, animated, {count} {frames}, {} ms",
+            self.total_duration()
+        )
+    }
+}
+
 impl std::fmt::Debug for WebpInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("WebpInfo")
@@ -73,3 +90,19 @@ impl std::fmt::Debug for WebpInfo {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displays_size_frames_and_duration() {
+        let animated =
+            WebpInfo::from_stdout("  Canvas size 112 x 112\n  Duration: 40\n  Duration: 60\n")
+                .unwrap();
+        assert_eq!(animated.to_string(), "112x112, animated, 2 frames, 100 ms");
+
+        let still = WebpInfo::from_stdout("  Canvas size 128 x 64\n").unwrap();
+        assert_eq!(still.to_string(), "128x64, static");
+    }
+}
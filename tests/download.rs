@@ -0,0 +1,362 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use convertoid::config::{Http, Settings};
+use convertoid::context::Context;
+use convertoid::download::{Client, Fetched, Validators};
+use convertoid::emote::Emote;
+use convertoid::error::HttpError;
+use convertoid::source::{Fixture, Format, Sources};
+use tempfile::TempDir;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+fn client() -> Client {
+    Client::new(&Http {
+        timeout_secs: 1,
+        retries: 2,
+        backoff_ms: 1,
+        max_backoff_ms: 10,
+        per_host_interval_ms: 0,
+        ..Default::default()
+    })
+}
+
+fn webp() -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_raw(b"RIFF".to_vec(), "image/webp")
+}
+
+#[tokio::test]
+async fn fails_on_404_without_retrying() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404).set_body_string("<html>not found</html>"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let err = client()
+        .get_image(&format!("{}/emote/4x.webp", server.uri()))
+        .await
+        .unwrap_err();
+    match err.downcast_ref::<HttpError>() {
+        Some(HttpError::Status { status, .. }) => assert_eq!(status.as_u16(), 404),
+        _ => panic!("unexpected error: {err}"),
+    }
+}
+
+#[tokio::test]
+async fn retries_server_errors() {
+    let server = MockServer::start().await;
+    Mock::given(path("/emote/4x.webp"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(path("/emote/4x.webp"))
+        .respond_with(webp())
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let bytes = client()
+        .get_image(&format!("{}/emote/4x.webp", server.uri()))
+        .await
+        .unwrap();
+    assert_eq!(&bytes[..], b"RIFF");
+}
+
+#[tokio::test]
+async fn gives_up_after_the_last_retry() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let err = client().get_bytes(&server.uri()).await.unwrap_err();
+    match err.downcast_ref::<HttpError>() {
+        Some(HttpError::Status { status, .. }) => assert_eq!(status.as_u16(), 500),
+        _ => panic!("unexpected error: {err}"),
+    }
+}
+
+#[tokio::test]
+async fn honours_retry_after() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(webp())
+        .mount(&server)
+        .await;
+
+    let client = Client::new(&Http {
+        max_backoff_ms: 2000,
+        ..Default::default()
+    });
+    let start = Instant::now();
+    client.get_image(&server.uri()).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn gives_up_when_retry_after_exceeds_max_backoff() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503).insert_header("Retry-After", "999999999"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let start = Instant::now();
+    let err = client().get_bytes(&server.uri()).await.unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(1));
+    match err.downcast_ref::<HttpError>() {
+        Some(HttpError::Status { status, .. }) => assert_eq!(status.as_u16(), 503),
+        _ => panic!("unexpected error: {err}"),
+    }
+}
+
+#[tokio::test]
+async fn rejects_responses_that_are_not_images() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("<html></html>", "text/html"))
+        .mount(&server)
+        .await;
+
+    let err = client().get_image(&server.uri()).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<HttpError>(),
+        Some(HttpError::ContentType { content_type, .. }) if content_type == "text/html"
+    ));
+}
+
+#[tokio::test]
+async fn times_out_slow_responses() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(webp().set_delay(Duration::from_secs(3)))
+        .mount(&server)
+        .await;
+
+    let client = Client::new(&Http {
+        timeout_secs: 1,
+        retries: 0,
+        ..Default::default()
+    });
+    let err = client.get_image(&server.uri()).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<HttpError>(),
+        Some(HttpError::Request { source, .. }) if source.is_timeout()
+    ));
+}
+
+#[tokio::test]
+async fn spaces_out_requests_to_the_same_host() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(webp())
+        .mount(&server)
+        .await;
+
+    let client = Client::new(&Http {
+        per_host_concurrency: 1,
+        per_host_interval_ms: 200,
+        ..Default::default()
+    });
+    let start = Instant::now();
+    for _ in 0..3 {
+        client.get_image(&server.uri()).await.unwrap();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(400));
+}
+
+/// A file in the temporary directory that's unique to the test
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("convertoid-{}-{name}", std::process::id()))
+}
+
+#[tokio::test]
+async fn revalidates_with_etag() {
+    let server = MockServer::start().await;
+    Mock::given(path("/emote/4x.webp"))
+        .and(header("If-None-Match", "\"v1\""))
+        .respond_with(ResponseTemplate::new(304))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(path("/emote/4x.webp"))
+        .respond_with(webp().insert_header("ETag", "\"v1\""))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let url = format!("{}/emote/4x.webp", server.uri());
+    let file = temp_file("revalidate.part");
+    let validators = match client()
+        .download_image(&url, &Validators::default(), &file)
+        .await
+        .unwrap()
+    {
+        Fetched::Modified { validators, .. } => validators,
+        Fetched::NotModified => panic!("unconditional request wasn't answered"),
+    };
+    assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+
+    let fetched = client()
+        .download_image(&url, &validators, &file)
+        .await
+        .unwrap();
+    assert!(matches!(fetched, Fetched::NotModified));
+    std::fs::remove_file(file).unwrap();
+}
+
+#[tokio::test]
+async fn revalidates_with_last_modified() {
+    let last_modified = "Wed, 01 Mar 2023 12:00:00 GMT";
+    let server = MockServer::start().await;
+    Mock::given(path("/emote/4x.webp"))
+        // wiremock splits header values at commas, which dates contain
+        .and(move |request: &Request| {
+            let mut headers = request.headers.iter();
+            headers.any(|(name, values)| {
+                let values: Vec<&str> = values.iter().map(|value| value.as_str()).collect();
+                name.as_str().eq_ignore_ascii_case("If-Modified-Since")
+                    && values.join(", ") == last_modified
+            })
+        })
+        .respond_with(ResponseTemplate::new(304))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(path("/emote/4x.webp"))
+        .respond_with(webp().insert_header("Last-Modified", last_modified))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let url = format!("{}/emote/4x.webp", server.uri());
+    let file = temp_file("last-modified.part");
+    let validators = match client()
+        .download_image(&url, &Validators::default(), &file)
+        .await
+        .unwrap()
+    {
+        Fetched::Modified { validators, .. } => validators,
+        Fetched::NotModified => panic!("unconditional request wasn't answered"),
+    };
+    assert_eq!(validators.etag, None);
+    assert_eq!(validators.last_modified.as_deref(), Some(last_modified));
+
+    let fetched = client()
+        .download_image(&url, &validators, &file)
+        .await
+        .unwrap();
+    assert!(matches!(fetched, Fetched::NotModified));
+    std::fs::remove_file(file).unwrap();
+}
+
+/// A context that refreshes the downloads of the `fixture` source in `dir`
+fn refreshing_context(dir: &Path) -> Context {
+    let mut settings = Settings::default();
+    settings.dirs.download = dir.join("dl");
+    settings.dirs.raw_frames = dir.join("frames-raw");
+    settings.dirs.resized_frames = dir.join("frames-resized");
+    settings.dirs.out_static = dir.join("out-static");
+    settings.dirs.out_anim = dir.join("out-anim");
+    // nothing is run, downloading and refreshing don't need the binaries
+    let binaries = &mut settings.binaries;
+    for path in [
+        &mut binaries.anim_dump,
+        &mut binaries.webp_info,
+        &mut binaries.ffmpeg,
+        &mut binaries.img2webp,
+    ] {
+        *path = Some(dir.join("unused"));
+    }
+    std::fs::create_dir_all(&settings.dirs.download).unwrap();
+
+    let mut sources = Sources::default();
+    sources.register(Fixture::new(dir.join("fixtures")));
+    Context::builder()
+        .settings(settings)
+        .sources(sources)
+        .refresh(true)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn refresh_invalidates_only_changed_emotes() {
+    let dir = TempDir::new().unwrap();
+    let fixtures = dir.path().join("fixtures");
+    std::fs::create_dir_all(&fixtures).unwrap();
+    std::fs::write(fixtures.join("pepe.webp"), b"RIFF\x04\x00\x00\x00WEBPVP8 ").unwrap();
+    let ctx = refreshing_context(dir.path());
+    let id = ctx.sources.parse("fixture", "pepe").unwrap();
+
+    Emote::download(&ctx, &id).await.unwrap();
+    let frames = ctx.raw_frames_path(&id);
+    let sticker = ctx.static_out_path(&id);
+    std::fs::create_dir_all(&frames).unwrap();
+    std::fs::write(frames.join("0000.png"), b"frame").unwrap();
+    std::fs::create_dir_all(sticker.parent().unwrap()).unwrap();
+    std::fs::write(&sticker, b"sticker").unwrap();
+
+    // the same content keeps the frames and the sticker
+    Emote::download(&ctx, &id).await.unwrap();
+    assert!(frames.join("0000.png").exists());
+    assert!(sticker.exists());
+
+    let changed = b"RIFF\x08\x00\x00\x00WEBPVP8 \x00\x00\x00\x00";
+    std::fs::write(fixtures.join("pepe.webp"), changed).unwrap();
+    Emote::download(&ctx, &id).await.unwrap();
+    assert!(!frames.exists());
+    assert!(!sticker.exists());
+    assert_eq!(std::fs::read(ctx.download_path(&id)).unwrap(), changed);
+}
+
+#[tokio::test]
+async fn aborts_bodies_over_the_size_limit() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(vec![0; 1000], "image/gif"))
+        .mount(&server)
+        .await;
+
+    let client = Client::new(&Http {
+        max_body_size: 100,
+        ..Default::default()
+    });
+    let err = client.get_image(&server.uri()).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<HttpError>(),
+        Some(HttpError::TooLarge { max_size: 100, .. })
+    ));
+}
+
+#[tokio::test]
+async fn sniffs_the_format_of_streamed_bodies() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_raw(b"GIF89a\x01\x00\x01\x00".to_vec(), "image/webp"),
+        )
+        .mount(&server)
+        .await;
+
+    let file = temp_file("sniff.part");
+    let body = client().get(&server.uri(), &file).await.unwrap();
+    assert_eq!(body.format, Some(Format::Gif));
+    assert_eq!(body.size, 10);
+    assert_eq!(std::fs::read(&file).unwrap().len(), 10);
+    std::fs::remove_file(file).unwrap();
+}
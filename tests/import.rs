@@ -0,0 +1,169 @@
+#![cfg(unix)]
+
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use convertoid::config::Settings;
+use convertoid::context::Context;
+use convertoid::import::{import, plan};
+use convertoid::source::{Fixture, Sources};
+use tempfile::TempDir;
+use zip::write::FileOptions;
+
+/// Smallest content that's sniffed as WebP
+const WEBP: &[u8] = b"RIFF\x04\x00\x00\x00WEBPVP8 ";
+
+/// A context working in `dir` whose `webpinfo` accepts any file
+fn context(dir: &Path, max_body_size: u64, offline: bool) -> Context {
+    let webpinfo = dir.join("webpinfo");
+    std::fs::write(&webpinfo, "#!/bin/sh\necho '  Canvas size 128 x 128'\n").unwrap();
+    std::fs::set_permissions(&webpinfo, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut settings = Settings::default();
+    settings.dirs.download = dir.join("dl");
+    settings.dirs.raw_frames = dir.join("frames-raw");
+    settings.dirs.resized_frames = dir.join("frames-resized");
+    settings.dirs.out_static = dir.join("out-static");
+    settings.dirs.out_anim = dir.join("out-anim");
+    for path in [
+        &mut settings.binaries.anim_dump,
+        &mut settings.binaries.ffmpeg,
+        &mut settings.binaries.img2webp,
+    ] {
+        *path = Some("/bin/false".into());
+    }
+    settings.binaries.webp_info = Some(webpinfo);
+    settings.http.max_body_size = max_body_size;
+    std::fs::create_dir_all(&settings.dirs.download).unwrap();
+
+    let mut sources = Sources::default();
+    sources.register(Fixture::new(dir.join("fixtures")));
+    Context::builder()
+        .settings(settings)
+        .sources(sources)
+        .offline(offline)
+        .build()
+        .unwrap()
+}
+
+fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    for (name, data) in entries {
+        zip.start_file(*name, FileOptions::default()).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+#[tokio::test]
+async fn imports_a_folder() {
+    let dir = TempDir::new().unwrap();
+    let folder = dir.path().join("shared/fixture");
+    std::fs::create_dir_all(&folder).unwrap();
+    std::fs::write(folder.join("pepe.webp"), WEBP).unwrap();
+    std::fs::write(folder.join("notes.txt"), "not an emote").unwrap();
+    let ctx = context(dir.path(), 1024, true);
+
+    let summary = import(&ctx, &dir.path().join("shared"), None)
+        .await
+        .unwrap();
+    assert_eq!(summary.outcomes.len(), 1);
+    assert_eq!(summary.count("succeeded"), 1);
+    let imported = std::fs::read(dir.path().join("dl/fixture-pepe.webp")).unwrap();
+    assert_eq!(imported, WEBP);
+    assert!(dir.path().join("dl/fixture-pepe.webp.http.json").exists());
+
+    // importing the same file again doesn't change anything
+    let summary = import(&ctx, &dir.path().join("shared"), None)
+        .await
+        .unwrap();
+    assert_eq!(summary.count("succeeded"), 1);
+}
+
+#[tokio::test]
+async fn identifies_downloaded_file_names() {
+    let dir = TempDir::new().unwrap();
+    let folder = dir.path().join("shared");
+    std::fs::create_dir_all(&folder).unwrap();
+    std::fs::write(folder.join("fixture-pepe.webp"), WEBP).unwrap();
+    let ctx = context(dir.path(), 1024, true);
+
+    let files = plan(&ctx, &folder, None).await.unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(format!("{:?}", files[0].1), "fixture:pepe");
+}
+
+#[tokio::test]
+async fn plans_without_writing() {
+    let dir = TempDir::new().unwrap();
+    let folder = dir.path().join("shared/fixture");
+    std::fs::create_dir_all(&folder).unwrap();
+    std::fs::write(folder.join("pepe.webp"), WEBP).unwrap();
+    let ctx = context(dir.path(), 1024, true);
+
+    let files = plan(&ctx, &dir.path().join("shared"), None).await.unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].0, Path::new("fixture/pepe.webp"));
+    assert_eq!(format!("{:?}", files[0].1), "fixture:pepe");
+    assert!(!dir.path().join("dl/fixture-pepe.webp").exists());
+}
+
+#[tokio::test]
+async fn skips_archive_entries_outside_of_it() {
+    let dir = TempDir::new().unwrap();
+    let archive = dir.path().join("dl.zip");
+    write_zip(
+        &archive,
+        &[
+            ("../escaped.webp", WEBP),
+            ("/absolute.webp", WEBP),
+            ("fixture/pepe.webp", WEBP),
+        ],
+    );
+    let ctx = context(dir.path(), 1024, true);
+
+    let summary = import(&ctx, &archive, None).await.unwrap();
+    assert_eq!(summary.outcomes.len(), 1);
+    assert_eq!(format!("{:?}", summary.outcomes[0].0), "fixture:pepe");
+    assert!(dir.path().join("dl/fixture-pepe.webp").exists());
+    assert!(!dir.path().join("escaped.webp").exists());
+    assert!(!dir.path().join("dl/fixture-escaped.webp").exists());
+    assert!(!dir.path().join("dl/fixture-absolute.webp").exists());
+}
+
+#[tokio::test]
+async fn rejects_oversized_archive_entries() {
+    let dir = TempDir::new().unwrap();
+    let archive = dir.path().join("dl.zip");
+    let mut large = WEBP.to_vec();
+    large.resize(256, 0);
+    write_zip(
+        &archive,
+        &[("fixture/large.webp", &large), ("fixture/small.webp", WEBP)],
+    );
+    let ctx = context(dir.path(), 128, true);
+
+    let summary = import(&ctx, &archive, None).await.unwrap();
+    assert_eq!(summary.outcomes.len(), 2);
+    assert_eq!(summary.count("succeeded"), 1);
+    let (id, outcome) = &summary.outcomes[0];
+    assert_eq!(format!("{id:?}"), "fixture:large");
+    assert!(outcome.reason().unwrap().contains("larger than 128 bytes"));
+    assert!(!dir.path().join("dl/fixture-large.webp").exists());
+    assert!(dir.path().join("dl/fixture-small.webp").exists());
+}
+
+#[tokio::test]
+async fn fails_on_offline_cache_miss() {
+    let dir = TempDir::new().unwrap();
+    let fixtures = dir.path().join("fixtures");
+    std::fs::create_dir_all(&fixtures).unwrap();
+    std::fs::write(fixtures.join("pepe.webp"), WEBP).unwrap();
+    let ctx = context(dir.path(), 1024, true);
+
+    let id = ctx.sources.parse("fixture", "pepe").unwrap();
+    let err = ctx.fetch_emote(&id).await.unwrap_err();
+    assert!(err.to_string().contains("can't be fetched offline"));
+    assert!(!dir.path().join("dl/fixture-pepe.webp").exists());
+}
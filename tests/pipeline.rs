@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use convertoid::binaries::Binaries;
+use convertoid::config::Settings;
+use convertoid::context::Context;
+use convertoid::image::{write_png, Image};
+use convertoid::pipeline::convert_one;
+use convertoid::source::{Fixture, Sources};
+use tempfile::TempDir;
+
+/// A red disc on a transparent 96x96 canvas
+fn disc() -> Image {
+    let size = 96;
+    let mut rgba = vec![0; size * size * 4];
+    for (index, px) in rgba.chunks_exact_mut(4).enumerate() {
+        let (x, y) = ((index % size) as f32 - 47.5, (index / size) as f32 - 47.5);
+        if x * x + y * y < 40.0 * 40.0 {
+            px.copy_from_slice(&[220, 30, 30, 255]);
+        }
+    }
+    Image {
+        width: size,
+        height: size,
+        rgba,
+    }
+}
+
+fn settings(dir: &Path) -> Settings {
+    let mut settings = Settings::default();
+    let dirs = &mut settings.dirs;
+    dirs.download = dir.join("dl");
+    dirs.raw_frames = dir.join("frames-raw");
+    dirs.resized_frames = dir.join("frames-resized");
+    dirs.out_static = dir.join("out-static");
+    dirs.out_anim = dir.join("out-anim");
+    for dir in [
+        &dirs.download,
+        &dirs.raw_frames,
+        &dirs.resized_frames,
+        &dirs.out_static,
+        &dirs.out_anim,
+    ] {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    settings
+}
+
+#[tokio::test]
+async fn converts_a_fixture_emote() {
+    let dir = TempDir::new().unwrap();
+    let settings = settings(dir.path());
+    let bin = match Binaries::new(&settings.binaries) {
+        Ok(bin) if bin.magick().is_ok() => bin,
+        _ => {
+            eprintln!("skipping, the external binaries aren't installed");
+            return;
+        }
+    };
+    let fixtures = dir.path().join("fixtures");
+    std::fs::create_dir_all(&fixtures).unwrap();
+    write_png(&disc(), &fixtures.join("disc.png")).unwrap();
+
+    let mut sources = Sources::default();
+    sources.register(Fixture::new(&fixtures));
+    let ctx = Context::builder()
+        .settings(settings)
+        .sources(sources)
+        .bin(bin)
+        .build()
+        .unwrap();
+    let id = ctx.sources.parse("fixture", "disc").unwrap();
+
+    let sticker = convert_one(&ctx, &id).await.unwrap();
+    assert_eq!(sticker.path, ctx.static_out_path(&id));
+    assert!(sticker.size <= ctx.settings.limits.static_size);
+    let info = ctx.bin.webp_info.info(&sticker.path).await.unwrap();
+    assert_eq!(info.size, ctx.settings.sticker_size());
+    assert!(!info.is_animated());
+    assert_eq!(ctx.emote_name(&id).await.as_deref(), Some("disc"));
+}